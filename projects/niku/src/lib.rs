@@ -11,8 +11,10 @@ use log::debug;
 pub mod backend;
 pub mod object;
 pub mod peer;
+pub mod progress;
 use std::path::PathBuf;
 pub(crate) const CACHE_PREFIX: &str = "app.niku";
+pub(crate) const BLOB_STORE_SUBFOLDER: &str = "blobs";
 
 /// Get the system dependant user cache storage path.
 pub fn get_cache_path() -> PathBuf {
@@ -24,6 +26,14 @@ pub fn get_cache_path() -> PathBuf {
     cache_path
 }
 
+/// Get the path of the persistent [iroh_blobs] store inside the user cache.
+pub fn get_blob_store_path() -> PathBuf {
+    let mut blob_store_path = get_cache_path();
+    blob_store_path.push(BLOB_STORE_SUBFOLDER);
+
+    blob_store_path
+}
+
 /// Get the correct backend address given its prefix.
 pub(crate) fn get_backend_address_from_prefix(prefix: &str) -> Option<String> {
     let url = match prefix {
@@ -35,6 +45,11 @@ pub(crate) fn get_backend_address_from_prefix(prefix: &str) -> Option<String> {
     url.map(String::from)
 }
 
+/// Get the correct backend address given the ID of an object.
+pub(crate) fn get_backend_address_from_id(id: &str) -> Option<String> {
+    get_backend_address_from_prefix(id.split('-').next()?)
+}
+
 pub(crate) fn get_recommended_backend_address() -> String {
     String::from(if cfg!(debug_assertions) {
         debug!("Debug mode enabled, trying to use local backend...");
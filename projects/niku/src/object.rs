@@ -8,12 +8,30 @@
 
 use std::borrow::Cow;
 use std::fmt::{Debug, Display};
+use std::str::FromStr;
 
 use iroh::NodeAddr;
-use iroh_blobs::Hash;
+use iroh_blobs::{BlobFormat, Hash};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use crate::peer::PeerError;
+
+/// Number of digits of the secret at the end of an [ObjectCode].
+const OBJECT_SECRET_DIGITS: usize = 6;
+
+/// Name used for the objects whose name is empty once sanitized.
+const DEFAULT_OBJECT_NAME: &str = "object";
+
+/// Maximum number of bytes of a file name in most file systems.
+const MAX_FILE_NAME_BYTES: usize = 255;
+
+/// Names of devices that can't be used as file names on Windows, even with an extension.
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 /// The kind of an object
 pub enum ObjectKind {
@@ -22,6 +40,12 @@ pub enum ObjectKind {
 
     /// The object is a folder
     Folder,
+
+    /// The object is a bundle of several files and folders
+    Bundle,
+
+    /// The object is a short text, that is shown instead of saved into a file
+    Text,
 }
 
 impl Display for ObjectKind {
@@ -29,12 +53,59 @@ impl Display for ObjectKind {
         let text = match self {
             ObjectKind::File => "file",
             ObjectKind::Folder => "folder",
+            ObjectKind::Bundle => "bundle",
+            ObjectKind::Text => "text",
+        };
+
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+/// The format used to transfer the content of a folder object
+pub enum FolderFormat {
+    /// An [iroh_blobs] collection with a blob for each file, transferred and verified individually
+    Collection,
+
+    /// A single zip archive with all the files
+    Zip,
+
+    /// A single tar archive with all the files
+    Tar,
+
+    /// A single tar archive with all the files, compressed with Zstandard
+    TarZst,
+}
+
+impl Display for FolderFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            FolderFormat::Collection => "collection",
+            FolderFormat::Zip => "zip",
+            FolderFormat::Tar => "tar",
+            FolderFormat::TarZst => "tar.zst",
         };
 
         write!(f, "{}", text)
     }
 }
 
+impl FromStr for FolderFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "collection" => Ok(FolderFormat::Collection),
+            "zip" => Ok(FolderFormat::Zip),
+            "tar" => Ok(FolderFormat::Tar),
+            "tar.zst" => Ok(FolderFormat::TarZst),
+            _ => Err(format!(
+                "Unknown folder format '{text}', expected 'collection', 'zip', 'tar' or 'tar.zst'"
+            )),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 /// Wrapper around the [NodeAddr] used to implement external traits.
 pub struct NodeAddrWrapper(pub(crate) NodeAddr);
@@ -60,7 +131,7 @@ impl utoipa::PartialSchema for NodeAddrWrapper {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 /// Wrapper around the [Hash] used to implement external traits.
 pub struct HashWrapper(pub(crate) Hash);
 
@@ -102,4 +173,236 @@ pub struct ObjectEntry {
 
     /// The number of bytes of the object.
     pub size: u64,
+
+    /// The format used to transfer the folder, only present for folder objects.
+    pub folder_format: Option<FolderFormat>,
+
+    /// Summary of the files inside of the object, only present for folder and bundle objects.
+    pub contents: Option<ObjectContents>,
+
+    /// Whether the receivers must know the password of the object to download it.
+    #[serde(default)]
+    pub requires_password: bool,
+}
+
+impl ObjectEntry {
+    /// The format of the blob that is referenced by the hash of the object.
+    pub(crate) fn blob_format(&self) -> BlobFormat {
+        match (&self.kind, self.folder_format) {
+            (ObjectKind::Bundle, _) | (ObjectKind::Folder, Some(FolderFormat::Collection)) => {
+                BlobFormat::HashSeq
+            }
+            (ObjectKind::File | ObjectKind::Text, _)
+            | (
+                ObjectKind::Folder,
+                Some(FolderFormat::Zip | FolderFormat::Tar | FolderFormat::TarZst) | None,
+            ) => BlobFormat::Raw,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+/// Summary of the files inside of a folder or bundle object, as described by its sender.
+pub struct ObjectContents {
+    /// The number of files inside of the object.
+    pub file_count: u64,
+
+    /// The hash of the blob with the [ObjectListing] of the object,
+    /// that can be retrieved before downloading the object.
+    pub listing_hash: HashWrapper,
+
+    /// The number of bytes of the blob with the [ObjectListing].
+    pub listing_size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+/// Listing of every file, folder and symbolic link inside of a folder or bundle object.
+pub struct ObjectListing {
+    /// The entries of the listing, sorted by their path.
+    pub entries: Vec<ListingEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Entry of an [ObjectListing].
+pub struct ListingEntry {
+    /// The path inside of the object, using `/` as separator.
+    pub path: String,
+
+    /// The kind of the entry.
+    pub kind: ListingEntryKind,
+
+    /// The number of bytes of the entry, only files have bytes.
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// The kind of a [ListingEntry].
+pub enum ListingEntryKind {
+    /// The entry is a file
+    File,
+
+    /// The entry is a folder
+    Folder,
+
+    /// The entry is a symbolic link
+    Symlink,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The code that the receivers of an object use to retrieve it, like `the-brave-cat-jumps-123456`.
+///
+/// It's made of the ID of the object in its backend followed by a secret of its sender,
+/// that never reaches the backend and is used to authenticate the sender and the object.
+pub struct ObjectCode {
+    /// The ID of the object in its backend.
+    pub id: String,
+
+    /// The secret of the sender of the object.
+    pub secret: String,
+}
+
+impl ObjectCode {
+    /// Make the code of an object with the given ID and a new random secret.
+    pub(crate) fn generate(id: String) -> ObjectCode {
+        let secret = rand::random_range(0..10u32.pow(OBJECT_SECRET_DIGITS as u32));
+
+        ObjectCode {
+            id,
+            secret: format!("{secret:0OBJECT_SECRET_DIGITS$}"),
+        }
+    }
+}
+
+impl Display for ObjectCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.id, self.secret)
+    }
+}
+
+impl FromStr for ObjectCode {
+    type Err = PeerError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (id, secret) = text.rsplit_once('-').ok_or(PeerError::InvalidId)?;
+
+        if id.is_empty()
+            || secret.len() != OBJECT_SECRET_DIGITS
+            || !secret.chars().all(|character| character.is_ascii_digit())
+        {
+            return Err(PeerError::InvalidId);
+        }
+
+        Ok(ObjectCode {
+            id: id.to_string(),
+            secret: secret.to_string(),
+        })
+    }
+}
+
+/// Sanitize the name of an object given by its sender, so it's a single file name
+/// that can be safely created inside of a folder on any platform.
+///
+/// Path separators, control characters and the characters reserved by Windows are replaced,
+/// leading and trailing dots and whitespaces are removed, so it's never hidden nor `..`,
+/// and the names reserved by Windows are prefixed.
+pub(crate) fn sanitize_object_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|character| match character {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            character if character.is_control() => '_',
+            character => character,
+        })
+        .collect();
+
+    let is_trimmed = |character: char| character == '.' || character.is_whitespace();
+    let mut name = name.trim_matches(is_trimmed).to_string();
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved_name| reserved_name.eq_ignore_ascii_case(stem))
+    {
+        name.insert(0, '_');
+    }
+
+    if name.len() > MAX_FILE_NAME_BYTES {
+        let mut end = MAX_FILE_NAME_BYTES;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        name.truncate(end);
+        name.truncate(name.trim_end_matches(is_trimmed).len());
+    }
+
+    if name.is_empty() {
+        DEFAULT_OBJECT_NAME.to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_code_round_trip() -> Result<(), PeerError> {
+        let code = ObjectCode::generate(String::from("the-brave-cat-jumps"));
+        assert_eq!(code.secret.len(), OBJECT_SECRET_DIGITS);
+        assert_eq!(code.to_string().parse::<ObjectCode>()?, code);
+
+        assert!("the-brave-cat-jumps".parse::<ObjectCode>().is_err());
+        assert!("the-brave-cat-jumps-12345".parse::<ObjectCode>().is_err());
+        assert!("-123456".parse::<ObjectCode>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sanitize_object_name_keeps_safe_names() {
+        assert_eq!(sanitize_object_name("photo 1.jpg"), "photo 1.jpg");
+        assert_eq!(sanitize_object_name("archive.tar.gz"), "archive.tar.gz");
+        assert_eq!(sanitize_object_name("añoranza ✓"), "añoranza ✓");
+    }
+
+    #[test]
+    fn test_sanitize_object_name_of_paths() {
+        assert_eq!(sanitize_object_name("../../.bashrc"), "_.._.bashrc");
+        assert_eq!(sanitize_object_name("/etc/passwd"), "_etc_passwd");
+        assert_eq!(sanitize_object_name("..\\..\\evil.exe"), "_.._evil.exe");
+        assert_eq!(sanitize_object_name("C:\\Windows"), "C__Windows");
+    }
+
+    #[test]
+    fn test_sanitize_object_name_of_dots_and_empty_names() {
+        assert_eq!(sanitize_object_name(""), DEFAULT_OBJECT_NAME);
+        assert_eq!(sanitize_object_name("."), DEFAULT_OBJECT_NAME);
+        assert_eq!(sanitize_object_name(".."), DEFAULT_OBJECT_NAME);
+        assert_eq!(sanitize_object_name(" .bashrc "), "bashrc");
+        assert_eq!(sanitize_object_name("name. . "), "name");
+    }
+
+    #[test]
+    fn test_sanitize_object_name_of_control_characters() {
+        assert_eq!(sanitize_object_name("red\u{1b}[31m"), "red_[31m");
+        assert_eq!(sanitize_object_name("line\nbreak\0"), "line_break_");
+    }
+
+    #[test]
+    fn test_sanitize_object_name_of_windows_reserved_names() {
+        assert_eq!(sanitize_object_name("CON"), "_CON");
+        assert_eq!(sanitize_object_name("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize_object_name("com1 .tar.gz"), "_com1 .tar.gz");
+        assert_eq!(sanitize_object_name("CONSOLE"), "CONSOLE");
+    }
+
+    #[test]
+    fn test_sanitize_object_name_of_long_names() {
+        let name = sanitize_object_name(&"ñ".repeat(200));
+
+        assert_eq!(name.len(), 254);
+        assert!(name.chars().all(|character| character == 'ñ'));
+    }
 }
@@ -6,29 +6,292 @@
 
 //! Code that implements a NIKU peer.
 
+mod approval;
+mod bundle;
+mod collection;
 mod file;
 mod folder;
+mod listing;
+mod metadata;
+mod output;
+mod pake;
+mod provider;
 mod request;
+mod text;
+mod walk;
 
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
 use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures_lite::StreamExt;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use iroh::protocol::Router;
-use iroh::Endpoint;
-use iroh_blobs::net_protocol::Blobs;
+use iroh::{Endpoint, NodeId};
+use iroh_blobs::hashseq::HashSeq;
+use iroh_blobs::net_protocol::{Blobs, DownloadMode};
+use iroh_blobs::rpc::client::blobs::DownloadOptions;
+use iroh_blobs::store::{ExportFormat, ExportMode, GcConfig, MapEntry, MapMut, Store};
+use iroh_blobs::util::SetTagOption;
+use iroh_blobs::{BlobFormat, Hash, HashAndFormat, Tag};
 use log::debug;
 use reqwest::Method;
 use thiserror::Error;
+use tokio::fs;
+use tokio::sync::{broadcast, mpsc, oneshot};
 use zip::result::ZipError;
 
+use self::approval::{Gate, GatedBlobs, Handshake};
+use self::pake::ObjectSecret;
+use self::provider::{handle_provider_events, ProviderEventSender};
 use crate::backend::{ErrorResponse, ObjectKeepAliveRequest, RegisteredObjectData};
-use crate::object::ObjectEntry;
+use crate::object::{sanitize_object_name, FolderFormat, ObjectCode, ObjectEntry, ObjectKind};
+use crate::progress::{DownloadProgress, ExportProgress, ProviderEvent};
+
+/// Maximum number of bytes of a text object, so it can be shown at once.
+pub const MAX_TEXT_BYTES: u64 = 64 * 1024;
+
+/// Maximum number of bytes of the listing of an object that is retrieved before downloading it.
+pub const MAX_LISTING_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Period of the garbage collection of the persistent blob store,
+/// that removes the blobs of the objects whose data has been removed.
+const GC_PERIOD_SECONDS: u64 = 60;
+
+/// ALPN of the NIKU handshake, used by the receivers to authenticate the sender of an object and be approved by it.
+pub const HANDSHAKE_ALPN: &[u8] = b"niku/handshake/1";
 
 /// Peer used to interact with other NIKU clients.
 pub struct Peer {
     client: reqwest::Client,
-    blobs: Blobs<iroh_blobs::store::mem::Store>,
+    blobs: Blobs<iroh_blobs::store::fs::Store>,
     router: Router,
+    gate: Arc<Gate>,
+    provider_events: broadcast::Sender<ProviderEvent>,
+    ephemeral_store_path: Option<PathBuf>,
+}
+
+/// Options used to pack files and folders into an object.
+#[derive(Debug, Clone)]
+pub struct PackOptions {
+    /// Preserve the permissions and modification times of the files.
+    pub preserve_metadata: bool,
+
+    /// Follow the symbolic links, packing their targets instead of the links themselves.
+    pub follow_links: bool,
+
+    /// Skip the files ignored by the `.gitignore` and `.nikuignore` files, and the `.git` folders.
+    pub use_ignore_files: bool,
+
+    /// Globs of the files to be packed, relative to the packed folder,
+    /// if there is any the rest of files are skipped.
+    pub include: Vec<String>,
+
+    /// Globs of the files to be skipped, relative to the packed folder.
+    pub exclude: Vec<String>,
+
+    /// The compression of the folders packed into an archive, the default one of the format if [None].
+    pub compression: Option<Compression>,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        PackOptions {
+            preserve_metadata: true,
+            follow_links: false,
+            use_ignore_files: true,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            compression: None,
+        }
+    }
+}
+
+/// The compression of the files packed into an archive, with an optional level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// The files are stored without compression.
+    Store,
+
+    /// Deflate, with a level from 0 to 9.
+    Deflate(Option<i32>),
+
+    /// Zstandard, with a level from 1 to 22.
+    Zstd(Option<i32>),
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::Store => write!(f, "store"),
+            Compression::Deflate(None) => write!(f, "deflate"),
+            Compression::Deflate(Some(level)) => write!(f, "deflate:{level}"),
+            Compression::Zstd(None) => write!(f, "zstd"),
+            Compression::Zstd(Some(level)) => write!(f, "zstd:{level}"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    /// Parse a compression method, optionally followed by its level, like `zstd:19`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (method, level) = match text.split_once(':') {
+            Some((method, level)) => (
+                method,
+                Some(
+                    level
+                        .parse()
+                        .map_err(|_| format!("Invalid compression level '{level}'"))?,
+                ),
+            ),
+            None => (text, None),
+        };
+
+        match (method, level) {
+            ("store", None) => Ok(Compression::Store),
+            ("store", Some(_)) => Err("The 'store' compression has no level".to_string()),
+            ("deflate", level) => Ok(Compression::Deflate(level)),
+            ("zstd", level) => Ok(Compression::Zstd(level)),
+            _ => Err(format!(
+                "Unknown compression '{method}', expected 'store', 'deflate' or 'zstd'"
+            )),
+        }
+    }
+}
+
+/// What to do when the output path of an exported object, or of an item of a bundle, already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Replace the existing file or folder.
+    Overwrite,
+
+    /// Export with a new name, adding a number to the existing one.
+    Rename,
+
+    /// Don't export it.
+    Skip,
+
+    /// Fail with [PeerError::OutputAlreadyExists] before exporting anything.
+    Fail,
+}
+
+impl Display for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::Rename => "rename",
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::Fail => "fail",
+        };
+
+        write!(f, "{}", text)
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "rename" => Ok(ConflictPolicy::Rename),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "fail" => Ok(ConflictPolicy::Fail),
+            _ => Err(format!(
+                "Unknown conflict policy '{text}', expected 'overwrite', 'rename', 'skip' or 'fail'"
+            )),
+        }
+    }
+}
+
+/// Limits of the extraction of the archives of received folders,
+/// that protect against archives that expand to much more data than the transferred one.
+#[derive(Debug, Clone)]
+pub struct ExtractLimits {
+    /// Maximum number of bytes that can be extracted, only limited by the ratio if [None].
+    pub max_bytes: Option<u64>,
+
+    /// Maximum ratio between the number of extracted bytes and the size of the archive.
+    pub max_ratio: u64,
+
+    /// Maximum number of files, folders and symbolic links that can be extracted.
+    pub max_entries: u64,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        ExtractLimits {
+            max_bytes: None,
+            max_ratio: 100,
+            max_entries: 1_000_000,
+        }
+    }
+}
+
+/// Selection of the files and folders of a folder or bundle object that are downloaded and exported,
+/// with globs matched against their paths inside of the object.
+///
+/// A file or folder is selected if its path, or the path of any of its parent folders, matches a glob.
+#[derive(Debug, Clone)]
+pub struct EntrySelection {
+    globs: GlobSet,
+}
+
+impl EntrySelection {
+    /// Make a selection of the entries that match any of the given globs,
+    /// where `*` never matches the path separator `/`.
+    pub fn new(globs: &[String]) -> Result<EntrySelection, PeerError> {
+        let mut builder = GlobSetBuilder::new();
+
+        for glob in globs {
+            builder.add(GlobBuilder::new(glob).literal_separator(true).build()?);
+        }
+
+        Ok(EntrySelection {
+            globs: builder.build()?,
+        })
+    }
+
+    /// Whether the entry with the given path, relative to the object, is selected.
+    pub(crate) fn is_selected(&self, path: &Path) -> bool {
+        path.ancestors()
+            .filter(|path| !path.as_os_str().is_empty())
+            .any(|path| self.globs.is_match(path))
+    }
+}
+
+/// Request of a receiver to download the objects of the peer, that must be accepted or declined.
+///
+/// Dropping the request declines it.
+#[derive(Debug)]
+pub struct ApprovalRequest {
+    /// The [iroh] node ID of the receiver.
+    pub node_id: NodeId,
+
+    /// The address used to connect with the receiver, like `direct(1.2.3.4:5678)` or `relay(https://...)`.
+    pub address: String,
+
+    responder: oneshot::Sender<bool>,
+}
+
+impl ApprovalRequest {
+    /// Allow the receiver to download the objects of the peer.
+    pub fn accept(self) {
+        let _ = self.responder.send(true);
+    }
+
+    /// Forbid the receiver to download the objects of the peer.
+    pub fn decline(self) {
+        let _ = self.responder.send(false);
+    }
 }
 
 /// Errors that may happen when interacting with an NIKU peer.
@@ -73,18 +336,153 @@ pub enum PeerError {
     /// The given ID is invalid.
     #[error("The given ID is invalid")]
     InvalidId,
+
+    /// Unable to walk the files to be packed, or their ignore rules are invalid.
+    #[error("Unable to walk the files to be packed: {0}")]
+    WalkError(#[from] ignore::Error),
+
+    /// The compression is not supported by the format of the folder.
+    #[error("The '{compression}' compression is not supported by the '{format}' format")]
+    UnsupportedCompression {
+        /// The format of the folder.
+        format: FolderFormat,
+
+        /// The unsupported compression.
+        compression: Compression,
+    },
+
+    /// Several items of a bundle have the same name.
+    #[error("Several items have the same name: '{0}'")]
+    DuplicatedItemName(String),
+
+    /// The output path of an export already exists.
+    #[error("The output path '{}' already exists", .0.display())]
+    OutputAlreadyExists(PathBuf),
+
+    /// A path of a folder would be written through a symbolic link, so it may be malicious.
+    #[error("The path '{}' goes through a symbolic link", .0.display())]
+    PathThroughSymlink(PathBuf),
+
+    /// A symbolic link of a folder points outside of the folder, so it may be malicious.
+    #[error("The symbolic link '{}' points outside of its folder: '{target}'", .path.display())]
+    SymlinkOutsideOfDestination {
+        /// The path of the symbolic link.
+        path: PathBuf,
+
+        /// The target of the symbolic link.
+        target: String,
+    },
+
+    /// The archive of a folder exceeds the extraction limits, so it may be malicious.
+    #[error("The extraction of the folder was aborted, its archive has {0}")]
+    ExtractLimitExceeded(String),
+
+    /// The text of an object is longer than [MAX_TEXT_BYTES].
+    #[error("The text is too long, texts can't have more than {MAX_TEXT_BYTES} bytes")]
+    TextTooLong,
+
+    /// The received text is not encoded with UTF-8 (Unicode).
+    #[error("The received text is not encoded with UTF-8 (Unicode)")]
+    NotUnicodeText,
+
+    /// A glob of an [EntrySelection] is invalid.
+    #[error("Invalid glob: {0}")]
+    InvalidGlob(#[from] globset::Error),
+
+    /// No file or folder of the object is selected.
+    #[error("No file or folder of the object matches the selection")]
+    NothingSelected,
+
+    /// The listing of an object is longer than [MAX_LISTING_BYTES].
+    #[error("The listing of the object is too long, listings can't have more than {MAX_LISTING_BYTES} bytes")]
+    ListingTooLong,
+
+    /// The sender of the object has declined the download.
+    #[error("The download has been declined by the sender")]
+    DeclinedBySender,
+
+    /// The object has reached its limit of downloads.
+    #[error("The object has already been downloaded as many times as its sender allows")]
+    DownloadLimitReached,
+
+    /// The object is protected with a password, but none has been given.
+    #[error("The object is protected with a password, but none has been given")]
+    PasswordRequired,
+
+    /// The given password of the object is wrong.
+    #[error("The password of the object is wrong")]
+    WrongPassword,
+
+    /// The sender and the object couldn't be authenticated with the secret of the code of the object.
+    #[error("The sender couldn't be authenticated, the ID may be wrong or the object may have been tampered with")]
+    AuthenticationFailed,
+
+    /// The object hasn't been approved by its sender yet.
+    #[error("The object hasn't been authenticated and approved by its sender")]
+    NotApproved,
 }
 
 impl Peer {
-    /// Make a new [Peer].
+    /// Make a new [Peer] backed by the persistent blob store of the user cache.
+    ///
+    /// Only one [Peer] at a time can use the persistent blob store.
     pub async fn new() -> Result<Peer, PeerError> {
+        Peer::with_store_path(&crate::get_blob_store_path(), false).await
+    }
+
+    /// Make a new [Peer] backed by its own blob store, that is deleted when the peer is shutdown.
+    ///
+    /// Useful when the peer is only going to send objects, as the files are referenced in place.
+    pub async fn new_ephemeral() -> Result<Peer, PeerError> {
+        let ephemeral_store_path = Peer::create_temporal_path("ephemeral-stores", "").await?;
+
+        Peer::with_store_path(&ephemeral_store_path, true).await
+    }
+
+    async fn with_store_path(store_path: &Path, is_ephemeral: bool) -> Result<Peer, PeerError> {
         let client = reqwest::Client::new();
         let endpoint = Endpoint::builder().bind().await?;
 
-        let blobs = Blobs::memory().build(&endpoint);
+        debug!("Loading the blob store at '{}'...", store_path.display());
+        let gate = Arc::new(Gate::default());
+        let (message_sender, messages) = mpsc::unbounded_channel();
+        let blobs = Blobs::persistent(store_path)
+            .await?
+            .events(
+                ProviderEventSender {
+                    messages: message_sender.clone(),
+                    gate: gate.clone(),
+                }
+                .into(),
+            )
+            .build(&endpoint);
+
+        // The ephemeral stores are removed as a whole
+        if !is_ephemeral {
+            blobs.start_gc(GcConfig {
+                period: Duration::from_secs(GC_PERIOD_SECONDS),
+                done_callback: None,
+            })?;
+        }
+
+        let provider_events = Peer::make_provider_event_channel();
+        tokio::spawn(handle_provider_events(messages, provider_events.clone()));
+
+        let handshake = Handshake {
+            endpoint: endpoint.clone(),
+            gate: gate.clone(),
+            messages: message_sender.clone(),
+        };
+
+        let gated_blobs = GatedBlobs {
+            blobs: blobs.clone(),
+            gate: gate.clone(),
+            messages: message_sender,
+        };
 
         let router = Router::builder(endpoint)
-            .accept(iroh_blobs::ALPN, blobs.clone())
+            .accept(HANDSHAKE_ALPN, handshake)
+            .accept(iroh_blobs::ALPN, gated_blobs)
             .spawn()
             .await?;
 
@@ -92,33 +490,88 @@ impl Peer {
             client,
             blobs,
             router,
+            gate,
+            provider_events,
+            ephemeral_store_path: is_ephemeral.then(|| store_path.to_owned()),
         })
     }
 
+    /// Get a new unique path inside the given subfolder of the cache, creating its parent folders.
+    pub(crate) async fn create_temporal_path(
+        subfolder_name: &str,
+        suffix: &str,
+    ) -> Result<PathBuf, PeerError> {
+        let now: DateTime<Utc> = SystemTime::now().into();
+
+        let mut temporal_path = crate::get_cache_path();
+        temporal_path.push(format!("{subfolder_name}/{}{suffix}", now.format("%+")));
+
+        fs::create_dir_all(temporal_path.parent().ok_or(PeerError::FolderIsRoot)?).await?;
+
+        Ok(temporal_path)
+    }
+
+    /// Get the path where an object entry is exported:
+    /// the custom output path or its sanitized name inside of the current folder.
+    pub(crate) fn get_output_path(
+        object_entry: &ObjectEntry,
+        custom_output_path: &Option<PathBuf>,
+    ) -> Result<PathBuf, PeerError> {
+        match custom_output_path {
+            Some(custom_output_path) => Ok(custom_output_path.clone()),
+            None => Ok(std::env::current_dir()?.join(sanitize_object_name(&object_entry.name))),
+        }
+    }
+
     /// Safetly shutdown the peer.
     pub async fn async_drop(self) -> Result<(), PeerError> {
         debug!("Shuting down the peer...");
-        Ok(self.router.shutdown().await?)
+        self.router.shutdown().await?;
+
+        if let Some(ephemeral_store_path) = self.ephemeral_store_path {
+            debug!("Removing the ephemeral blob store...");
+            fs::remove_dir_all(ephemeral_store_path).await?;
+        }
+
+        Ok(())
     }
 
     /// Publish an object entry to the most available backend server.
+    ///
+    /// Returns the code of the object, whose secret never reaches the backend,
+    /// that the receivers use to authenticate the peer and the object, see [Peer::request_approval].
     pub async fn publish_object_entry(
         &self,
         object_entry: &ObjectEntry,
-    ) -> Result<RegisteredObjectData, PeerError> {
-        self.request_expect_json(Method::PUT, "objects", Some(object_entry), None)
-            .await
-    }
-
-    /// Retrieve an object from the correct backend.
-    pub async fn retrieve_object_entry(&self, id: &str) -> Result<ObjectEntry, PeerError> {
-        self.request_expect_json(
-            Method::GET,
-            &format!("objects/{id}"),
-            None::<&()>,
-            Some(crate::get_backend_address_from_prefix(id).ok_or(PeerError::InvalidId)?),
-        )
-        .await
+    ) -> Result<(RegisteredObjectData, ObjectCode), PeerError> {
+        let registered_object_entry: RegisteredObjectData = self
+            .request_expect_json(Method::PUT, "objects", Some(object_entry), None)
+            .await?;
+
+        let code = ObjectCode::generate(registered_object_entry.id.clone());
+        *self.gate.secret.lock().await = Some(ObjectSecret::new(&code, object_entry)?);
+
+        Ok((registered_object_entry, code))
+    }
+
+    /// Retrieve an object from the correct backend given its code.
+    ///
+    /// The name of the object is sanitized, as it's given by its sender.
+    /// Nothing of the object is trusted until its sender has been authenticated, see [Peer::request_approval].
+    pub async fn retrieve_object_entry(&self, code: &ObjectCode) -> Result<ObjectEntry, PeerError> {
+        let id = &code.id;
+        let mut object_entry: ObjectEntry = self
+            .request_expect_json(
+                Method::GET,
+                &format!("objects/{id}"),
+                None::<&()>,
+                Some(crate::get_backend_address_from_id(id).ok_or(PeerError::InvalidId)?),
+            )
+            .await?;
+
+        object_entry.name = sanitize_object_name(&object_entry.name);
+
+        Ok(object_entry)
     }
 
     /// Keep alive the given object entry.
@@ -133,7 +586,28 @@ impl Peer {
                 keep_alive_key: registered_object_entry.keep_alive_key.clone(),
             }),
             Some(
-                crate::get_backend_address_from_prefix(&registered_object_entry.id)
+                crate::get_backend_address_from_id(&registered_object_entry.id)
+                    .ok_or(PeerError::InvalidId)?,
+            ),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Unregister the given object entry from its backend, so no new receiver can find it.
+    pub async fn unregister_object_entry(
+        &self,
+        registered_object_entry: &RegisteredObjectData,
+    ) -> Result<(), PeerError> {
+        self.request(
+            Method::DELETE,
+            &format!("objects/{}", registered_object_entry.id),
+            Some(&ObjectKeepAliveRequest {
+                keep_alive_key: registered_object_entry.keep_alive_key.clone(),
+            }),
+            Some(
+                crate::get_backend_address_from_id(&registered_object_entry.id)
                     .ok_or(PeerError::InvalidId)?,
             ),
         )
@@ -143,17 +617,391 @@ impl Peer {
     }
 
     /// Download an object entry into the Iroh store.
-    pub async fn download_object_entry(&self, object_entry: &ObjectEntry) -> Result<(), PeerError> {
+    ///
+    /// If the object has been partially downloaded before only the missing data is requested,
+    /// the already available data is verified against the hash of the object.
+    ///
+    /// With a selection, only the selected files of the folders sent as a collection and of the bundles are downloaded,
+    /// the archives of the rest of folders are downloaded completely.
+    ///
+    /// The object must have been approved by its sender already, see [Peer::request_approval].
+    pub async fn download_object_entry(
+        &self,
+        object_entry: &ObjectEntry,
+        selection: Option<&EntrySelection>,
+        mut on_progress: impl FnMut(DownloadProgress),
+    ) -> Result<(), PeerError> {
+        use iroh_blobs::get::db::DownloadProgress as IrohDownloadProgress;
+
+        if matches!(object_entry.kind, ObjectKind::Text) && object_entry.size > MAX_TEXT_BYTES {
+            return Err(PeerError::TextTooLong);
+        }
+
+        self.check_approval(object_entry).await?;
+
+        // Tagged beforehand, so the partial downloads are kept by the garbage collection
         self.blobs
+            .store()
+            .set_tag(
+                Peer::get_download_tag(object_entry),
+                Some(HashAndFormat {
+                    hash: object_entry.file_hash.0,
+                    format: object_entry.blob_format(),
+                }),
+            )
+            .await?;
+
+        if let Some(selection) = selection {
+            if object_entry.blob_format() == BlobFormat::HashSeq {
+                return self
+                    .download_selected_collection_files(object_entry, selection, &mut on_progress)
+                    .await;
+            }
+        }
+
+        let mut download = self
+            .blobs
             .client()
-            .download(
+            .download_with_opts(
                 object_entry.file_hash.0,
-                object_entry.node_address.0.clone(),
+                DownloadOptions {
+                    format: object_entry.blob_format(),
+                    nodes: vec![object_entry.node_address.0.clone()],
+                    tag: SetTagOption::Named(Peer::get_download_tag(object_entry)),
+                    mode: DownloadMode::Queued,
+                },
+            )
+            .await?;
+
+        on_progress(DownloadProgress::Total(object_entry.size));
+
+        // The blobs that are counted in the size of the object,
+        // the hash sequence of a collection and its metadata are not,
+        // so the children of the collection are added once the hash sequence is found
+        let mut counted_blobs: HashSet<Hash> = match object_entry.blob_format() {
+            BlobFormat::Raw => HashSet::from([object_entry.file_hash.0]),
+            BlobFormat::HashSeq => HashSet::new(),
+        };
+
+        // The blobs being downloaded indexed by their progress ID, with their hash and size
+        let mut found_blobs: HashMap<u64, (Hash, u64)> = HashMap::new();
+
+        // The bytes available of each blob indexed by their hash
+        let mut available_bytes: HashMap<Hash, u64> = HashMap::new();
+
+        while let Some(event) = download.next().await {
+            match event? {
+                IrohDownloadProgress::Connected => on_progress(DownloadProgress::Connected),
+
+                IrohDownloadProgress::FoundHashSeq { .. } => {
+                    // The first child is the metadata of the collection
+                    counted_blobs.extend(self.get_hash_seq_children(object_entry).await?.skip(1));
+                }
+
+                IrohDownloadProgress::FoundLocal {
+                    hash,
+                    size,
+                    valid_ranges,
+                    ..
+                } if counted_blobs.contains(&hash) => {
+                    let boundaries: Vec<u64> = valid_ranges
+                        .to_chunk_ranges()
+                        .boundaries()
+                        .iter()
+                        .map(|chunk| chunk.to_bytes())
+                        .collect();
+
+                    available_bytes.insert(hash, count_available_bytes(&boundaries, size.value()));
+                    on_progress(DownloadProgress::Received(available_bytes.values().sum()));
+                }
+
+                IrohDownloadProgress::Found { id, hash, size, .. }
+                    if counted_blobs.contains(&hash) =>
+                {
+                    found_blobs.insert(id, (hash, size));
+                }
+
+                IrohDownloadProgress::Progress { id, offset } => {
+                    if let Some((hash, _)) = found_blobs.get(&id) {
+                        let bytes = available_bytes.entry(*hash).or_default();
+                        *bytes = offset.max(*bytes);
+
+                        on_progress(DownloadProgress::Received(available_bytes.values().sum()));
+                    }
+                }
+
+                IrohDownloadProgress::Done { id } => {
+                    if let Some((hash, size)) = found_blobs.get(&id) {
+                        available_bytes.insert(*hash, *size);
+
+                        on_progress(DownloadProgress::Received(available_bytes.values().sum()));
+                    }
+                }
+
+                IrohDownloadProgress::AllDone(_) => {
+                    on_progress(DownloadProgress::Done);
+                    return Ok(());
+                }
+
+                IrohDownloadProgress::Abort(err) => return Err(anyhow::Error::from(err).into()),
+
+                IrohDownloadProgress::InitialState(_)
+                | IrohDownloadProgress::FoundLocal { .. }
+                | IrohDownloadProgress::Found { .. } => {}
+            }
+        }
+
+        Err(anyhow::anyhow!("The download ended unexpectedly").into())
+    }
+
+    /// Download a single blob from the node of an object entry into the Iroh store,
+    /// tagged with the download tag of the object.
+    ///
+    /// Reports the size of the blob and the number of its bytes available so far.
+    async fn download_blob(
+        &self,
+        object_entry: &ObjectEntry,
+        hash: Hash,
+        on_progress: &mut impl FnMut(DownloadProgress),
+    ) -> Result<(), PeerError> {
+        use iroh_blobs::get::db::DownloadProgress as IrohDownloadProgress;
+
+        let mut download = self
+            .blobs
+            .client()
+            .download_with_opts(
+                hash,
+                DownloadOptions {
+                    format: BlobFormat::Raw,
+                    nodes: vec![object_entry.node_address.0.clone()],
+                    tag: SetTagOption::Named(Peer::get_blob_download_tag(object_entry)),
+                    mode: DownloadMode::Queued,
+                },
+            )
+            .await?;
+
+        let mut size = 0;
+
+        while let Some(event) = download.next().await {
+            match event? {
+                IrohDownloadProgress::Connected => on_progress(DownloadProgress::Connected),
+
+                IrohDownloadProgress::FoundLocal {
+                    size: found_size,
+                    valid_ranges,
+                    ..
+                } => {
+                    let boundaries: Vec<u64> = valid_ranges
+                        .to_chunk_ranges()
+                        .boundaries()
+                        .iter()
+                        .map(|chunk| chunk.to_bytes())
+                        .collect();
+
+                    size = found_size.value();
+                    on_progress(DownloadProgress::Total(size));
+                    on_progress(DownloadProgress::Received(count_available_bytes(
+                        &boundaries,
+                        size,
+                    )));
+                }
+
+                IrohDownloadProgress::Found {
+                    size: found_size, ..
+                } => {
+                    size = found_size;
+                    on_progress(DownloadProgress::Total(size));
+                }
+
+                IrohDownloadProgress::Progress { offset, .. } => {
+                    on_progress(DownloadProgress::Received(offset))
+                }
+
+                IrohDownloadProgress::Done { .. } => on_progress(DownloadProgress::Received(size)),
+
+                IrohDownloadProgress::AllDone(_) => {
+                    on_progress(DownloadProgress::Done);
+                    return Ok(());
+                }
+
+                IrohDownloadProgress::Abort(err) => return Err(anyhow::Error::from(err).into()),
+
+                IrohDownloadProgress::InitialState(_)
+                | IrohDownloadProgress::FoundHashSeq { .. } => {}
+            }
+        }
+
+        Err(anyhow::anyhow!("The download ended unexpectedly").into())
+    }
+
+    /// Export a blob of the Iroh store to the given path.
+    ///
+    /// Doesn't send [ExportProgress::Done] as the export may continue after the blob is exported.
+    pub(crate) async fn export_blob(
+        &self,
+        hash: Hash,
+        output_path: &Path,
+        on_progress: &mut impl FnMut(ExportProgress),
+    ) -> Result<(), PeerError> {
+        use iroh_blobs::export::ExportProgress as IrohExportProgress;
+
+        let mut export = self
+            .blobs
+            .client()
+            .export(
+                hash,
+                output_path.to_owned(),
+                ExportFormat::Blob,
+                ExportMode::Copy,
             )
-            .await?
-            .finish()
             .await?;
 
+        let mut size = 0;
+
+        while let Some(event) = export.next().await {
+            match event? {
+                IrohExportProgress::Found {
+                    size: found_size, ..
+                } => {
+                    size = found_size.value();
+                    on_progress(ExportProgress::Total(size));
+                }
+
+                IrohExportProgress::Progress { offset, .. } => {
+                    on_progress(ExportProgress::Exported(offset))
+                }
+
+                IrohExportProgress::Done { .. } => on_progress(ExportProgress::Exported(size)),
+
+                IrohExportProgress::AllDone => return Ok(()),
+
+                IrohExportProgress::Abort(err) => return Err(anyhow::Error::from(err).into()),
+            }
+        }
+
+        Err(anyhow::anyhow!("The export ended unexpectedly").into())
+    }
+
+    /// Get the number of bytes of an object entry that are already available in the Iroh store,
+    /// for example from a previous interrupted download.
+    pub async fn get_available_bytes(&self, object_entry: &ObjectEntry) -> Result<u64, PeerError> {
+        match object_entry.blob_format() {
+            BlobFormat::Raw => {
+                self.get_blob_available_bytes(&object_entry.file_hash.0)
+                    .await
+            }
+
+            BlobFormat::HashSeq => {
+                let mut available_bytes = 0;
+
+                // The first child is the metadata of the collection
+                for hash in self.get_hash_seq_children(object_entry).await?.skip(1) {
+                    available_bytes += self.get_blob_available_bytes(&hash).await?;
+                }
+
+                Ok(available_bytes)
+            }
+        }
+    }
+
+    async fn get_blob_available_bytes(&self, hash: &Hash) -> Result<u64, PeerError> {
+        let Some(entry) = self.blobs.store().get_mut(hash).await? else {
+            return Ok(0);
+        };
+
+        let size = entry.size().value();
+
+        if entry.is_complete() {
+            return Ok(size);
+        }
+
+        let valid_ranges =
+            iroh_blobs::get::db::valid_ranges::<iroh_blobs::store::fs::Store>(&entry).await?;
+
+        let boundaries: Vec<u64> = valid_ranges
+            .boundaries()
+            .iter()
+            .map(|chunk| chunk.to_bytes())
+            .collect();
+
+        Ok(count_available_bytes(&boundaries, size))
+    }
+
+    /// Get the hashes of the children of a hash sequence object entry,
+    /// that are known only if the hash sequence has been downloaded.
+    async fn get_hash_seq_children(
+        &self,
+        object_entry: &ObjectEntry,
+    ) -> Result<impl Iterator<Item = Hash>, PeerError> {
+        let client = self.blobs.client();
+
+        if !client.has(object_entry.file_hash.0).await? {
+            return Ok(HashSeq::from_iter([]).into_iter());
+        }
+
+        let hash_seq = HashSeq::try_from(client.read_to_bytes(object_entry.file_hash.0).await?)?;
+
+        Ok(hash_seq.into_iter())
+    }
+
+    /// Remove the data of a downloaded object entry from the Iroh store.
+    ///
+    /// Only its tags are removed, so its blobs are reclaimed by the garbage collection of the store
+    /// unless they are shared with other downloaded objects.
+    pub async fn remove_object_entry_data(
+        &self,
+        object_entry: &ObjectEntry,
+    ) -> Result<(), PeerError> {
+        let tags = self.blobs.client().tags();
+
+        tags.delete(Peer::get_download_tag(object_entry)).await?;
+        tags.delete(Peer::get_blob_download_tag(object_entry))
+            .await?;
+
+        Ok(())
+    }
+
+    /// The tag that keeps the blobs of a downloaded object entry, even partially downloaded.
+    fn get_download_tag(object_entry: &ObjectEntry) -> Tag {
+        Tag::from(format!("download-{}", object_entry.file_hash.0))
+    }
+
+    /// The tag of the last blob downloaded alone from an object entry,
+    /// as every download is tagged, the blobs are kept by the download tag of the object.
+    fn get_blob_download_tag(object_entry: &ObjectEntry) -> Tag {
+        Tag::from(format!("download-{}-blob", object_entry.file_hash.0))
+    }
+}
+
+/// Count the bytes covered by the boundaries of a set of ranges of a blob of the given size.
+///
+/// The boundaries alternate between the start and the end of each range,
+/// a start without an end means that the range goes until the end of the blob.
+fn count_available_bytes(boundaries: &[u64], size: u64) -> u64 {
+    boundaries
+        .chunks(2)
+        .map(|range| match range {
+            [start, end] => end.min(&size).saturating_sub(*start),
+            [start] => size.saturating_sub(*start),
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_selection_matches_the_parent_folders() -> Result<(), PeerError> {
+        let selection = EntrySelection::new(&["docs/**".to_owned(), "*.md".to_owned()])?;
+
+        assert!(selection.is_selected(Path::new("docs/guide/intro.txt")));
+        assert!(selection.is_selected(Path::new("README.md")));
+        assert!(selection.is_selected(Path::new("notes.md/today.txt")));
+        assert!(!selection.is_selected(Path::new("src/README.md")));
+        assert!(!selection.is_selected(Path::new("docs")));
+
         Ok(())
     }
 }
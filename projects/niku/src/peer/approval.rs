@@ -0,0 +1,739 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use futures_lite::future::Boxed;
+use hmac::{Hmac, Mac};
+use iroh::endpoint::{Connecting, Connection, RecvStream, SendStream};
+use iroh::protocol::ProtocolHandler;
+use iroh::{Endpoint, NodeId};
+use iroh_blobs::net_protocol::Blobs;
+use log::debug;
+use sha2::Sha256;
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+
+use super::pake::{get_object_digest, ObjectSecret, Pake, Side, PAKE_MESSAGE_BYTES};
+use super::provider::{finish_download, DownloadLimit, ProviderMessage};
+use super::{ApprovalRequest, Peer, PeerError, HANDSHAKE_ALPN};
+use crate::object::{ObjectCode, ObjectEntry};
+
+/// Number of approval requests that can wait to be answered before blocking the handshakes.
+const APPROVAL_REQUEST_CAPACITY: usize = 16;
+
+/// Message sent by the receiver to start the handshake, followed by its PAKE message.
+const HANDSHAKE_REQUEST: u8 = 0;
+
+/// Message sent by the sender while it waits for the user to answer.
+const HANDSHAKE_ASKING: u8 = 1;
+
+/// Message sent by the sender when the receiver is approved.
+const HANDSHAKE_ACCEPTED: u8 = 2;
+
+/// Message sent by the sender when the receiver is declined.
+const HANDSHAKE_DECLINED: u8 = 3;
+
+/// Message sent by the sender when the object has reached its limit of downloads.
+const HANDSHAKE_EXHAUSTED: u8 = 4;
+
+/// Message sent by the sender, followed by a random challenge,
+/// when the receiver must prove that it knows the password of the object.
+const HANDSHAKE_CHALLENGE: u8 = 5;
+
+/// Message sent by the sender when the proof of the password of the receiver is wrong.
+const HANDSHAKE_WRONG_PASSWORD: u8 = 6;
+
+/// Message sent by the sender, followed by its PAKE message,
+/// so the receiver answers with its confirmation of the exchange.
+const HANDSHAKE_AUTHENTICATION: u8 = 7;
+
+/// Message sent by the sender once the confirmation of the receiver is right, followed by its own confirmation.
+const HANDSHAKE_CONFIRMATION: u8 = 8;
+
+/// Message sent by the sender when the receiver can't be authenticated.
+const HANDSHAKE_UNAUTHENTICATED: u8 = 9;
+
+/// Number of wrong confirmations or passwords of a receiver after which the sender stops authenticating it.
+const MAX_FAILED_AUTHENTICATIONS: u32 = 3;
+
+/// Number of wrong confirmations or passwords of all the receivers together after which the sender stops authenticating any receiver,
+/// so neither the secret of the object nor its password can be guessed with many node IDs, that are free to make.
+const MAX_TOTAL_FAILED_AUTHENTICATIONS: u32 = 20;
+
+/// Context of the proofs of the passwords, so they can't be used for anything else.
+const PASSWORD_PROOF_CONTEXT: &[u8] = b"niku/password-proof/0";
+
+/// Error code used to close the connections of the receivers that are not approved.
+const NOT_APPROVED_ERROR_CODE: u32 = 1;
+
+/// Shared state of the access to the objects of a peer, both as sender and as receiver.
+#[derive(Debug, Default)]
+pub(crate) struct Gate {
+    /// The channel where the approval requests are sent, only present when receivers must be approved.
+    asker: Mutex<Option<mpsc::Sender<ApprovalRequest>>>,
+
+    /// The password that the receivers must know, only present when the objects are protected.
+    password: Mutex<Option<String>>,
+
+    /// The secret of the published object, that the receivers must know to be authenticated.
+    pub(super) secret: Mutex<Option<ObjectSecret>>,
+
+    /// The number of attempts of each receiver to authenticate that haven't succeeded, including the ones in progress.
+    failed_authentications: Mutex<HashMap<NodeId, u32>>,
+
+    /// The receivers approved by this peer.
+    approved_nodes: Mutex<HashSet<NodeId>>,
+
+    /// The objects whose senders have been authenticated and have approved this peer,
+    /// indexed by the node ID of their sender and the digest of their description.
+    approving_objects: Mutex<HashSet<(NodeId, [u8; 32])>>,
+
+    /// The limit of downloads of an object, only present when the downloads are limited.
+    pub(super) download_limit: Mutex<Option<DownloadLimit>>,
+
+    /// Whether the limit of downloads has been reached, so no new receiver is served.
+    pub(super) is_exhausted: watch::Sender<bool>,
+
+    /// The number of connections of the receivers being served.
+    pub(super) served_connections: watch::Sender<u64>,
+
+    /// The connections of the receivers being served, indexed by their ID.
+    pub(super) connections: Mutex<HashMap<u64, Connection>>,
+}
+
+impl Gate {
+    /// Whether the given receiver can download the objects of the peer.
+    async fn is_approved(&self, node_id: &NodeId) -> bool {
+        !*self.is_exhausted.borrow() && self.approved_nodes.lock().await.contains(node_id)
+    }
+
+    /// Start an attempt of the given receiver to authenticate, if neither it nor all the receivers together
+    /// have run out of attempts.
+    ///
+    /// The attempt is counted as failed right away, so the attempts in progress count towards the limits too.
+    async fn start_authentication(&self, node_id: NodeId) -> bool {
+        let mut failed_authentications = self.failed_authentications.lock().await;
+
+        if failed_authentications.values().sum::<u32>() >= MAX_TOTAL_FAILED_AUTHENTICATIONS {
+            return false;
+        }
+
+        let failures = failed_authentications.entry(node_id).or_default();
+
+        if *failures >= MAX_FAILED_AUTHENTICATIONS {
+            return false;
+        }
+
+        *failures += 1;
+
+        true
+    }
+
+    /// Finish an attempt started with [Gate::start_authentication], that is no longer counted if it has succeeded.
+    ///
+    /// Returns the number of attempts that the receiver has left and whether no receiver can authenticate anymore.
+    async fn finish_authentication(&self, node_id: NodeId, is_authenticated: bool) -> (u32, bool) {
+        let mut failed_authentications = self.failed_authentications.lock().await;
+
+        let failures = failed_authentications.entry(node_id).or_default();
+
+        if is_authenticated {
+            *failures = failures.saturating_sub(1);
+        }
+
+        let remaining_attempts = MAX_FAILED_AUTHENTICATIONS.saturating_sub(*failures);
+        let is_locked =
+            failed_authentications.values().sum::<u32>() >= MAX_TOTAL_FAILED_AUTHENTICATIONS;
+
+        (remaining_attempts, is_locked)
+    }
+}
+
+/// Handler of the NIKU handshake, that authenticates each new receiver and asks for its approval.
+#[derive(Debug, Clone)]
+pub(crate) struct Handshake {
+    pub(crate) endpoint: Endpoint,
+    pub(crate) gate: Arc<Gate>,
+    pub(crate) messages: mpsc::UnboundedSender<ProviderMessage>,
+}
+
+impl Handshake {
+    /// Answer the handshake of a receiver, authenticating it,
+    /// checking its password and asking for its approval if needed.
+    async fn answer(&self, connection: Connection) -> Result<()> {
+        let node_id = connection.remote_node_id()?;
+        let (mut send, mut recv) = connection.accept_bi().await?;
+
+        let mut request = [0];
+        recv.read_exact(&mut request).await?;
+
+        let mut pake_message = [0; PAKE_MESSAGE_BYTES];
+        recv.read_exact(&mut pake_message).await?;
+
+        let is_authenticated = self
+            .authenticate(node_id, &pake_message, &mut send, &mut recv)
+            .await?;
+
+        let answer = if is_authenticated {
+            self.get_answer(node_id, &mut send, &mut recv).await?
+        } else {
+            HANDSHAKE_UNAUTHENTICATED
+        };
+
+        if answer == HANDSHAKE_ACCEPTED {
+            self.gate.approved_nodes.lock().await.insert(node_id);
+        }
+
+        debug!(
+            "The receiver {node_id} has been {}",
+            if answer == HANDSHAKE_ACCEPTED {
+                "approved"
+            } else {
+                "declined"
+            }
+        );
+
+        send.write_all(&[answer]).await?;
+        send.finish()?;
+
+        // The receiver closes the connection once it has read the answer
+        connection.closed().await;
+
+        Ok(())
+    }
+
+    /// Authenticate a receiver with the secret of the published object,
+    /// confirming the sender and the object to the receiver once the receiver has confirmed that it knows it.
+    ///
+    /// The receivers confirm first, so each of them can only check a single guess of the secret,
+    /// and the sender stops authenticating a receiver after [MAX_FAILED_AUTHENTICATIONS] wrong guesses,
+    /// and any receiver after [MAX_TOTAL_FAILED_AUTHENTICATIONS] wrong guesses of all of them.
+    ///
+    /// Each attempt is counted before the exchange starts, so concurrent handshakes can't exceed the limits,
+    /// and the attempts that are interrupted stay counted as failed.
+    async fn authenticate(
+        &self,
+        node_id: NodeId,
+        pake_message: &[u8; PAKE_MESSAGE_BYTES],
+        send: &mut SendStream,
+        recv: &mut RecvStream,
+    ) -> Result<bool> {
+        let secret = self.gate.secret.lock().await.clone();
+
+        let Some(secret) = secret else {
+            return Ok(false);
+        };
+
+        if !self.gate.start_authentication(node_id).await {
+            return Ok(false);
+        }
+
+        let pake = Pake::start(Side::Sender, secret);
+
+        send.write_all(&[HANDSHAKE_AUTHENTICATION]).await?;
+        send.write_all(&pake.message()).await?;
+
+        let confirmations = pake.finish(pake_message, &node_id, &self.endpoint.node_id())?;
+
+        let mut confirmation = [0; PAKE_MESSAGE_BYTES];
+        recv.read_exact(&mut confirmation).await?;
+
+        let is_authenticated = confirmations.verify_other(&confirmation)?;
+        self.finish_authentication(node_id, is_authenticated).await;
+
+        if !is_authenticated {
+            return Ok(false);
+        }
+
+        send.write_all(&[HANDSHAKE_CONFIRMATION]).await?;
+        send.write_all(&confirmations.own()?).await?;
+
+        Ok(true)
+    }
+
+    /// Finish an attempt of a receiver to authenticate, reporting it if it has failed.
+    async fn finish_authentication(&self, node_id: NodeId, is_authenticated: bool) {
+        let (remaining_attempts, is_locked) = self
+            .gate
+            .finish_authentication(node_id, is_authenticated)
+            .await;
+
+        if !is_authenticated {
+            // The messages are only dropped once the peer is shutting down
+            let _ = self.messages.send(ProviderMessage::AuthenticationFailed {
+                node_id,
+                remaining_attempts,
+                is_locked,
+            });
+        }
+    }
+
+    /// Get the final answer for an authenticated receiver, challenging it and asking the user in between if needed.
+    async fn get_answer(
+        &self,
+        node_id: NodeId,
+        send: &mut SendStream,
+        recv: &mut RecvStream,
+    ) -> Result<u8> {
+        if *self.gate.is_exhausted.borrow() {
+            return Ok(HANDSHAKE_EXHAUSTED);
+        }
+
+        if self.gate.approved_nodes.lock().await.contains(&node_id) {
+            return Ok(HANDSHAKE_ACCEPTED);
+        }
+
+        let password = self.gate.password.lock().await.clone();
+
+        if let Some(password) = password {
+            // The wrong passwords are limited like the wrong confirmations, so they can't be guessed either
+            if !self.gate.start_authentication(node_id).await {
+                return Ok(HANDSHAKE_DECLINED);
+            }
+
+            let challenge: [u8; 32] = rand::random();
+
+            send.write_all(&[HANDSHAKE_CHALLENGE]).await?;
+            send.write_all(&challenge).await?;
+
+            let mut proof = [0; 32];
+            recv.read_exact(&mut proof).await?;
+
+            let sender_node_id = self.endpoint.node_id();
+            let expected_proof =
+                get_password_proof(&password, &challenge, &node_id, &sender_node_id)?;
+
+            let is_right_password = expected_proof.verify_slice(&proof).is_ok();
+            self.finish_authentication(node_id, is_right_password).await;
+
+            if !is_right_password {
+                return Ok(HANDSHAKE_WRONG_PASSWORD);
+            }
+        }
+
+        let asker = self.gate.asker.lock().await.clone();
+
+        let Some(asker) = asker else {
+            return Ok(HANDSHAKE_ACCEPTED);
+        };
+
+        send.write_all(&[HANDSHAKE_ASKING]).await?;
+
+        let (responder, response) = oneshot::channel();
+        let address = self
+            .endpoint
+            .remote_info(node_id)
+            .map(|remote_info| remote_info.conn_type.to_string())
+            .unwrap_or_default();
+
+        let request = ApprovalRequest {
+            node_id,
+            address,
+            responder,
+        };
+
+        if asker.send(request).await.is_ok() && response.await.unwrap_or(false) {
+            Ok(HANDSHAKE_ACCEPTED)
+        } else {
+            Ok(HANDSHAKE_DECLINED)
+        }
+    }
+}
+
+impl ProtocolHandler for Handshake {
+    fn accept(&self, connecting: Connecting) -> Boxed<Result<()>> {
+        let handshake = self.clone();
+
+        Box::pin(async move { handshake.answer(connecting.await?).await })
+    }
+}
+
+/// Handler of the [iroh_blobs] protocol that only serves the receivers approved by the peer.
+#[derive(Debug, Clone)]
+pub(crate) struct GatedBlobs {
+    pub(crate) blobs: Blobs<iroh_blobs::store::fs::Store>,
+    pub(crate) gate: Arc<Gate>,
+    pub(crate) messages: mpsc::UnboundedSender<ProviderMessage>,
+}
+
+impl ProtocolHandler for GatedBlobs {
+    fn accept(&self, connecting: Connecting) -> Boxed<Result<()>> {
+        let gated_blobs = self.clone();
+
+        Box::pin(async move {
+            let connection = connecting.await?;
+            let node_id = connection.remote_node_id()?;
+
+            if !gated_blobs.gate.is_approved(&node_id).await {
+                debug!("Refusing the connection of the not approved receiver {node_id}");
+                connection.close(NOT_APPROVED_ERROR_CODE.into(), b"not approved");
+
+                return Ok(());
+            }
+
+            let connection_id = connection.stable_id() as u64;
+            let messages = &gated_blobs.messages;
+            let _ = messages.send(ProviderMessage::Connected {
+                connection_id,
+                node_id,
+            });
+
+            let gate = &gated_blobs.gate;
+            gate.connections
+                .lock()
+                .await
+                .insert(connection_id, connection.clone());
+            gate.served_connections.send_modify(|count| *count += 1);
+
+            let blobs = &gated_blobs.blobs;
+            iroh_blobs::provider::handle_connection(
+                connection,
+                blobs.store().clone(),
+                blobs.events().clone(),
+                blobs.rt().clone(),
+            )
+            .await;
+
+            gate.connections.lock().await.remove(&connection_id);
+            finish_download(gate, connection_id).await;
+            gate.served_connections.send_modify(|count| *count -= 1);
+            let _ = messages.send(ProviderMessage::Disconnected { connection_id });
+
+            Ok(())
+        })
+    }
+
+    fn shutdown(&self) -> Boxed<()> {
+        ProtocolHandler::shutdown(&self.blobs)
+    }
+}
+
+impl Peer {
+    /// Require the approval of each new receiver before it can download the objects of the peer.
+    ///
+    /// Returns the channel where the approval requests are received,
+    /// the receivers are declined once it's dropped.
+    pub async fn require_approval(&self) -> mpsc::Receiver<ApprovalRequest> {
+        let (asker, approval_requests) = mpsc::channel(APPROVAL_REQUEST_CAPACITY);
+
+        *self.gate.asker.lock().await = Some(asker);
+
+        approval_requests
+    }
+
+    /// Protect an object entry with a password, that the receivers must know to download it.
+    ///
+    /// The password is never sent, the receivers prove that they know it instead.
+    pub async fn protect_object_entry(&self, object_entry: &mut ObjectEntry, password: String) {
+        object_entry.requires_password = true;
+
+        *self.gate.password.lock().await = Some(password);
+    }
+
+    /// Authenticate the sender of an object entry with the code of the object,
+    /// and ask it for the approval to download the object,
+    /// with the password of the object if it's protected,
+    /// calling `on_waiting` if the sender is asking its user.
+    ///
+    /// Both peers confirm that they know the secret of the code, their node IDs and the description of the object,
+    /// so a tampered object entry makes the authentication fail instead of downloading something else.
+    ///
+    /// Senders that don't require approval accept immediately,
+    /// and the objects that have already been approved aren't asked again.
+    pub async fn request_approval(
+        &self,
+        object_entry: &ObjectEntry,
+        code: &ObjectCode,
+        password: Option<&str>,
+        on_waiting: impl FnOnce(),
+    ) -> Result<(), PeerError> {
+        let secret = ObjectSecret::new(code, object_entry)?;
+        let approved_object = (object_entry.node_address.0.node_id, secret.digest());
+
+        if self
+            .gate
+            .approving_objects
+            .lock()
+            .await
+            .contains(&approved_object)
+        {
+            return Ok(());
+        }
+
+        if object_entry.requires_password && password.is_none() {
+            return Err(PeerError::PasswordRequired);
+        }
+
+        let connection = self
+            .router
+            .endpoint()
+            .connect(object_entry.node_address.0.clone(), HANDSHAKE_ALPN)
+            .await?;
+
+        let answer = self
+            .read_handshake_answer(&connection, secret, password, on_waiting)
+            .await;
+        connection.close(0u32.into(), b"done");
+
+        match answer? {
+            HANDSHAKE_ACCEPTED => (),
+            HANDSHAKE_EXHAUSTED => return Err(PeerError::DownloadLimitReached),
+            HANDSHAKE_WRONG_PASSWORD => return Err(PeerError::WrongPassword),
+            HANDSHAKE_UNAUTHENTICATED => return Err(PeerError::AuthenticationFailed),
+            _ => return Err(PeerError::DeclinedBySender),
+        }
+
+        self.gate
+            .approving_objects
+            .lock()
+            .await
+            .insert(approved_object);
+
+        Ok(())
+    }
+
+    /// Whether an object entry has already been approved by its sender, see [Peer::request_approval].
+    pub async fn is_approved(&self, object_entry: &ObjectEntry) -> Result<bool, PeerError> {
+        let approved_object = (
+            object_entry.node_address.0.node_id,
+            get_object_digest(object_entry)?,
+        );
+
+        Ok(self
+            .gate
+            .approving_objects
+            .lock()
+            .await
+            .contains(&approved_object))
+    }
+
+    /// Check that an object entry has already been approved by its sender, see [Peer::request_approval].
+    pub(super) async fn check_approval(&self, object_entry: &ObjectEntry) -> Result<(), PeerError> {
+        if !self.is_approved(object_entry).await? {
+            return Err(PeerError::NotApproved);
+        }
+
+        Ok(())
+    }
+
+    /// Send the handshake request and read the answers of the sender until it gives its final answer,
+    /// authenticating the sender and proving that the peer knows the password if the sender challenges it.
+    ///
+    /// The answers of a sender that hasn't been authenticated are never accepted.
+    async fn read_handshake_answer(
+        &self,
+        connection: &Connection,
+        secret: ObjectSecret,
+        password: Option<&str>,
+        on_waiting: impl FnOnce(),
+    ) -> Result<u8> {
+        let (mut send, mut recv) = connection.open_bi().await?;
+
+        let pake = Pake::start(Side::Receiver, secret);
+
+        send.write_all(&[HANDSHAKE_REQUEST]).await?;
+        send.write_all(&pake.message()).await?;
+
+        let mut pake = Some(pake);
+        let mut confirmations = None;
+        let mut is_authenticated = false;
+        let mut on_waiting = Some(on_waiting);
+
+        loop {
+            let mut answer = [0];
+            recv.read_exact(&mut answer).await?;
+
+            match answer[0] {
+                HANDSHAKE_AUTHENTICATION => {
+                    let mut pake_message = [0; PAKE_MESSAGE_BYTES];
+                    recv.read_exact(&mut pake_message).await?;
+
+                    let pake = pake
+                        .take()
+                        .ok_or_else(|| anyhow!("The sender has authenticated twice"))?;
+                    let finished_confirmations = pake.finish(
+                        &pake_message,
+                        &self.router.endpoint().node_id(),
+                        &connection.remote_node_id()?,
+                    )?;
+
+                    send.write_all(&finished_confirmations.own()?).await?;
+                    confirmations = Some(finished_confirmations);
+                }
+
+                HANDSHAKE_CONFIRMATION => {
+                    let mut confirmation = [0; PAKE_MESSAGE_BYTES];
+                    recv.read_exact(&mut confirmation).await?;
+
+                    is_authenticated = match &confirmations {
+                        Some(confirmations) => confirmations.verify_other(&confirmation)?,
+                        None => false,
+                    };
+
+                    if !is_authenticated {
+                        send.finish()?;
+
+                        return Ok(HANDSHAKE_UNAUTHENTICATED);
+                    }
+                }
+
+                _ if !is_authenticated => {
+                    send.finish()?;
+
+                    // A sender that can't be authenticated can only make the download fail
+                    return Ok(match answer[0] {
+                        HANDSHAKE_ACCEPTED => HANDSHAKE_UNAUTHENTICATED,
+                        answer => answer,
+                    });
+                }
+
+                HANDSHAKE_ASKING => {
+                    if let Some(on_waiting) = on_waiting.take() {
+                        on_waiting();
+                    }
+                }
+
+                HANDSHAKE_CHALLENGE => {
+                    let mut challenge = [0; 32];
+                    recv.read_exact(&mut challenge).await?;
+
+                    // Without a password the proof is always wrong
+                    let proof = get_password_proof(
+                        password.unwrap_or_default(),
+                        &challenge,
+                        &self.router.endpoint().node_id(),
+                        &connection.remote_node_id()?,
+                    )?;
+
+                    send.write_all(&proof.finalize().into_bytes()).await?;
+                }
+
+                answer => {
+                    send.finish()?;
+
+                    return Ok(answer);
+                }
+            }
+        }
+    }
+}
+
+/// Get the proof that a receiver knows the password of an object,
+/// bound to the challenge of the sender and to both peers, so it can't be reused.
+fn get_password_proof(
+    password: &str,
+    challenge: &[u8; 32],
+    receiver_node_id: &NodeId,
+    sender_node_id: &NodeId,
+) -> Result<Hmac<Sha256>> {
+    let mut proof = Hmac::<Sha256>::new_from_slice(password.as_bytes())
+        .map_err(|error| anyhow!("Unable to use the password: {error}"))?;
+
+    proof.update(PASSWORD_PROOF_CONTEXT);
+    proof.update(challenge);
+    proof.update(receiver_node_id.as_bytes());
+    proof.update(sender_node_id.as_bytes());
+
+    Ok(proof)
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::SecretKey;
+
+    use super::*;
+
+    fn make_node_id() -> NodeId {
+        SecretKey::from_bytes(&rand::random()).public()
+    }
+
+    #[tokio::test]
+    async fn test_failed_authentications_are_counted_per_receiver() {
+        let gate = Gate::default();
+        let node_id = make_node_id();
+
+        // The attempts in progress are counted too
+        for _ in 0..MAX_FAILED_AUTHENTICATIONS {
+            assert!(gate.start_authentication(node_id).await);
+        }
+        assert!(!gate.start_authentication(node_id).await);
+
+        // Until they succeed
+        assert_eq!(gate.finish_authentication(node_id, true).await, (1, false));
+        assert!(gate.start_authentication(node_id).await);
+        assert_eq!(gate.finish_authentication(node_id, false).await, (0, false));
+
+        // A receiver that guesses wrong doesn't lock the other ones out
+        assert!(!gate.start_authentication(node_id).await);
+        assert!(gate.start_authentication(make_node_id()).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_authentications_are_limited() {
+        let gate = Arc::new(Gate::default());
+        let node_id = make_node_id();
+
+        let count_started = |node_ids: Vec<NodeId>| {
+            let handles: Vec<_> = node_ids
+                .into_iter()
+                .map(|node_id| {
+                    let gate = gate.clone();
+                    tokio::spawn(async move { gate.start_authentication(node_id).await })
+                })
+                .collect();
+
+            async move {
+                let mut started = 0;
+
+                for handle in handles {
+                    if handle.await.unwrap_or(false) {
+                        started += 1;
+                    }
+                }
+
+                started
+            }
+        };
+
+        // The same receiver opening many handshakes at once
+        let started = count_started(vec![node_id; 100]).await;
+        assert_eq!(started, MAX_FAILED_AUTHENTICATIONS);
+
+        // Many new receivers opening a handshake each at once
+        let started = count_started((0..100).map(|_| make_node_id()).collect()).await;
+        assert_eq!(
+            started,
+            MAX_TOTAL_FAILED_AUTHENTICATIONS - MAX_FAILED_AUTHENTICATIONS
+        );
+    }
+
+    #[test]
+    fn test_password_proof() -> Result<()> {
+        let receiver_node_id = make_node_id();
+        let sender_node_id = make_node_id();
+        let challenge: [u8; 32] = rand::random();
+
+        let proof = get_password_proof("secret", &challenge, &receiver_node_id, &sender_node_id)?
+            .finalize()
+            .into_bytes();
+
+        let verify = |password, receiver_node_id| -> Result<bool> {
+            Ok(
+                get_password_proof(password, &challenge, receiver_node_id, &sender_node_id)?
+                    .verify_slice(&proof)
+                    .is_ok(),
+            )
+        };
+
+        assert!(verify("secret", &receiver_node_id)?);
+        assert!(!verify("Secret", &receiver_node_id)?);
+        assert!(!verify("secret", &sender_node_id)?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::{ConflictPolicy, EntrySelection, PackOptions, Peer, PeerError};
+use crate::object::{HashWrapper, NodeAddrWrapper, ObjectEntry, ObjectKind};
+use crate::progress::{ExportProgress, PackProgress};
+
+impl Peer {
+    /// Creates a new object entry for a bundle of several files and folders.
+    ///
+    /// # Safety
+    /// Doesn't check if the given paths are for files or for folders.
+    pub async unsafe fn create_bundle_object_entry(
+        &mut self,
+        paths: Vec<PathBuf>,
+        options: &PackOptions,
+        mut on_progress: impl FnMut(PackProgress),
+    ) -> Result<ObjectEntry, PeerError> {
+        let mut item_names: Vec<String> = Vec::new();
+
+        for path in &paths {
+            let item_name = path
+                .file_name()
+                .ok_or(PeerError::FolderIsRoot)?
+                .to_str()
+                .ok_or(PeerError::NotUnicodePath)?
+                .to_string();
+
+            if item_names.contains(&item_name) {
+                return Err(PeerError::DuplicatedItemName(item_name));
+            }
+
+            item_names.push(item_name);
+        }
+
+        let items: Vec<(&str, &Path)> = item_names
+            .iter()
+            .map(String::as_str)
+            .zip(paths.iter().map(PathBuf::as_path))
+            .collect();
+
+        let (hash, size) = self
+            .create_collection(&items, options, &mut on_progress)
+            .await?;
+
+        let contents = self.create_listing(&items, options).await?;
+
+        on_progress(PackProgress::Done);
+
+        Ok(ObjectEntry {
+            node_address: NodeAddrWrapper(self.router.endpoint().node_addr().await?),
+            file_hash: HashWrapper(hash),
+            kind: ObjectKind::Bundle,
+            name: item_names.join(", "),
+            size,
+            folder_format: None,
+            contents: Some(contents),
+            requires_password: false,
+        })
+    }
+
+    /// Export a previously downloaded bundle object entry, placing its items inside the output folder.
+    ///
+    /// The conflicts with existing files and folders are resolved for each item with the given policy,
+    /// and with a selection only the selected files and folders are exported.
+    ///
+    /// # Safety
+    /// Doesn't check neither if the given object is for a bundle
+    /// or if the object has been downloaded beforehand into the Iroh store.
+    pub async unsafe fn export_bundle_object_entry(
+        &self,
+        object_entry: &ObjectEntry,
+        custom_output_path: &Option<PathBuf>,
+        on_conflict: ConflictPolicy,
+        selection: Option<&EntrySelection>,
+        mut on_progress: impl FnMut(ExportProgress),
+    ) -> Result<PathBuf, PeerError> {
+        let output_path = if let Some(custom_output_path) = custom_output_path {
+            custom_output_path.clone()
+        } else {
+            std::env::current_dir()?
+        };
+
+        self.export_collection(
+            object_entry,
+            &output_path,
+            on_conflict,
+            selection,
+            &mut on_progress,
+        )
+        .await?;
+
+        on_progress(ExportProgress::Done);
+
+        Ok(output_path)
+    }
+}
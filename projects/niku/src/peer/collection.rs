@@ -0,0 +1,553 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Result;
+use futures_lite::StreamExt;
+use iroh_blobs::format::collection::Collection;
+use iroh_blobs::rpc::client::blobs::WrapOption;
+use iroh_blobs::util::SetTagOption;
+use iroh_blobs::{Hash, Tag};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::metadata::{check_no_symlinks, create_dir_all, create_symlink, FileMetadata};
+use super::output::{remove_output, resolve_output_conflict};
+use super::walk::Walk;
+use super::{ConflictPolicy, EntrySelection, PackOptions, Peer, PeerError};
+use crate::object::ObjectEntry;
+use crate::progress::{DownloadProgress, ExportProgress, PackProgress};
+
+/// Name of the manifest inside of a collection,
+/// it's never a valid path so it's not exported as a file.
+const MANIFEST_NAME: &str = "/niku-manifest.json";
+
+/// Manifest with the entries of a collection that can't be represented only with its files.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct CollectionManifest {
+    entries: Vec<ManifestEntry>,
+}
+
+/// Entry of a [CollectionManifest], with the same name that it has inside of the collection.
+///
+/// The metadata is only present when it's preserved.
+#[derive(Serialize, Deserialize, Debug)]
+struct ManifestEntry {
+    name: String,
+    kind: ManifestEntryKind,
+    metadata: Option<FileMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum ManifestEntryKind {
+    File,
+    Folder,
+    Symlink { target: String },
+}
+
+impl Peer {
+    /// Add every file of the given items to the Iroh store, referenced in place, and group them in a collection.
+    ///
+    /// Each item is a file or a folder with the name that its files are placed under in the collection,
+    /// the files of an item with an empty name are placed at the root of the collection.
+    ///
+    /// The collection includes a manifest with its folders, so the empty ones are kept, and its symbolic links,
+    /// and also the metadata of each file and folder when the metadata is preserved.
+    ///
+    /// Returns the hash of the collection and its size, the bytes of its files and its manifest.
+    pub(super) async fn create_collection(
+        &self,
+        items: &[(&str, &Path)],
+        options: &PackOptions,
+        on_progress: &mut impl FnMut(PackProgress),
+    ) -> Result<(Hash, u64), PeerError> {
+        let mut files = Vec::new();
+        let mut manifest = CollectionManifest::default();
+
+        let mut skipped_entries = 0;
+        let mut skipped_bytes = 0;
+
+        for (item_name, item_path) in items {
+            let walk = Walk::new(item_path, options)?;
+            skipped_entries += walk.skipped_entries;
+            skipped_bytes += walk.skipped_bytes;
+
+            for entry in walk.entries {
+                let name = get_collection_name(item_name, &entry.relative_path)?;
+                let metadata = entry.metadata;
+
+                let kind = if entry.file_type.is_symlink() {
+                    let target = std::fs::read_link(&entry.path)?;
+                    let target = target.to_str().ok_or(PeerError::NotUnicodePath)?;
+
+                    ManifestEntryKind::Symlink {
+                        target: target.to_owned(),
+                    }
+                } else if entry.file_type.is_dir() {
+                    ManifestEntryKind::Folder
+                } else if entry.file_type.is_file() {
+                    files.push((name.clone(), entry.path, metadata.len()));
+
+                    ManifestEntryKind::File
+                } else {
+                    continue;
+                };
+
+                let metadata = options
+                    .preserve_metadata
+                    .then(|| FileMetadata::from_fs(&metadata));
+
+                // The root of the collection is the output folder,
+                // and the files are only needed for their metadata
+                if !name.is_empty()
+                    && (metadata.is_some() || !matches!(kind, ManifestEntryKind::File))
+                {
+                    manifest.entries.push(ManifestEntry {
+                        name,
+                        kind,
+                        metadata,
+                    });
+                }
+            }
+        }
+
+        on_progress(PackProgress::Skipped {
+            entries: skipped_entries,
+            bytes: skipped_bytes,
+        });
+
+        on_progress(PackProgress::Total(
+            files.iter().map(|(_, _, size)| size).sum(),
+        ));
+
+        let mut collection = Collection::default();
+        let mut tags = Vec::new();
+        let mut size = 0;
+
+        for (name, path, file_size) in files {
+            on_progress(PackProgress::File(name.clone()));
+
+            let (hash, tag) = self
+                .import_file(path, |offset| {
+                    on_progress(PackProgress::Packed(size + offset))
+                })
+                .await?;
+
+            collection.push(name, hash);
+            tags.push(tag);
+
+            size += file_size;
+            on_progress(PackProgress::Packed(size));
+        }
+
+        let manifest = serde_json::to_vec(&manifest)?;
+        let blob = self.blobs.client().add_bytes(manifest).await?;
+
+        collection.push(MANIFEST_NAME.to_owned(), blob.hash);
+        tags.push(blob.tag);
+
+        size += blob.size;
+
+        // The collection keeps its children alive, so their own tags are no longer needed
+        let (hash, _) = self
+            .blobs
+            .client()
+            .create_collection(collection, SetTagOption::Auto, tags)
+            .await?;
+
+        Ok((hash, size))
+    }
+
+    /// Export each file of the collection of an object entry into the output folder,
+    /// restoring the entries of its manifest if it has one.
+    ///
+    /// With a selection, only the selected files and entries of the manifest are exported.
+    ///
+    /// The conflicts with the existing files and folders are resolved for each item at the root of the collection.
+    pub(super) async fn export_collection(
+        &self,
+        object_entry: &ObjectEntry,
+        output_path: &Path,
+        on_conflict: ConflictPolicy,
+        selection: Option<&EntrySelection>,
+        on_progress: &mut impl FnMut(ExportProgress),
+    ) -> Result<(), PeerError> {
+        let collection = self
+            .blobs
+            .client()
+            .get_collection(object_entry.file_hash.0)
+            .await?;
+
+        let mut manifest = None;
+        let mut files = Vec::new();
+        let mut total = 0;
+
+        for (name, hash) in collection {
+            if name == MANIFEST_NAME {
+                let manifest_bytes = self.blobs.client().read_to_bytes(hash).await?;
+                manifest = Some(serde_json::from_slice::<CollectionManifest>(
+                    &manifest_bytes,
+                )?);
+            } else if let Some(relative_path) = get_enclosed_path(&name) {
+                files.push((relative_path, hash));
+            }
+        }
+
+        let is_selected =
+            |path: &Path| selection.is_none_or(|selection| selection.is_selected(path));
+
+        files.retain(|(relative_path, _)| is_selected(relative_path));
+
+        let manifest_entries: Vec<_> = manifest
+            .iter()
+            .flat_map(|manifest| &manifest.entries)
+            .filter_map(|entry| Some((get_enclosed_path(&entry.name)?, entry)))
+            .filter(|(relative_path, _)| is_selected(relative_path))
+            .collect();
+
+        if selection.is_some() && files.is_empty() && manifest_entries.is_empty() {
+            return Err(PeerError::NothingSelected);
+        }
+
+        // The conflicts are resolved for every item before exporting anything
+        let item_paths = resolve_item_conflicts(
+            output_path,
+            files
+                .iter()
+                .map(|(relative_path, _)| (relative_path.as_path(), false))
+                .chain(manifest_entries.iter().map(|(relative_path, entry)| {
+                    (
+                        relative_path.as_path(),
+                        matches!(entry.kind, ManifestEntryKind::Folder),
+                    )
+                })),
+            on_conflict,
+        )?;
+
+        let get_output_path = |relative_path: &Path| {
+            let mut components = relative_path.components();
+            let item_path = item_paths.get(components.next()?.as_os_str())?.as_ref()?;
+
+            // Joining an empty path would add a trailing separator
+            match components.as_path() {
+                path if path.as_os_str().is_empty() => Some(item_path.clone()),
+                path => Some(item_path.join(path)),
+            }
+        };
+
+        let files: Vec<_> = files
+            .into_iter()
+            .filter_map(|(relative_path, hash)| Some((get_output_path(&relative_path)?, hash)))
+            .collect();
+
+        for (_, hash) in &files {
+            total += self.get_blob_available_bytes(hash).await?;
+        }
+
+        on_progress(ExportProgress::Total(total));
+
+        // Create the folder beforehand, as it may not have any file
+        fs::create_dir_all(output_path).await?;
+
+        let mut exported_bytes = 0;
+
+        for (file_path, hash) in files {
+            check_no_symlinks(output_path, &file_path)?;
+
+            let mut file_size = 0;
+
+            self.export_blob(hash, &file_path, &mut |progress| match progress {
+                ExportProgress::Total(total) => file_size = total,
+                ExportProgress::Exported(exported) => {
+                    on_progress(ExportProgress::Exported(exported_bytes + exported))
+                }
+                ExportProgress::Extracting(_) | ExportProgress::Done => {}
+            })
+            .await?;
+
+            exported_bytes += file_size;
+        }
+
+        restore_manifest_entries(
+            output_path,
+            manifest_entries
+                .into_iter()
+                .filter_map(|(relative_path, entry)| {
+                    Some((get_output_path(&relative_path)?, entry))
+                }),
+        )?;
+
+        Ok(())
+    }
+
+    /// Download the selected files of the collection of an object entry into the Iroh store,
+    /// with the metadata and the manifest of the collection.
+    ///
+    /// The total number of bytes grows as the size of each selected file is found.
+    pub(super) async fn download_selected_collection_files(
+        &self,
+        object_entry: &ObjectEntry,
+        selection: &EntrySelection,
+        on_progress: &mut impl FnMut(DownloadProgress),
+    ) -> Result<(), PeerError> {
+        let hash = object_entry.file_hash.0;
+
+        on_progress(DownloadProgress::Total(0));
+
+        // The hash sequence and its metadata, the first child, have the names of the files
+        self.download_blob(object_entry, hash, &mut |_| {}).await?;
+
+        if let Some(metadata_hash) = self.get_hash_seq_children(object_entry).await?.next() {
+            self.download_blob(object_entry, metadata_hash, &mut |_| {})
+                .await?;
+        }
+
+        let collection = self.blobs.client().get_collection(hash).await?;
+
+        let selected_hashes: Vec<Hash> = collection
+            .iter()
+            .filter(|(name, _)| {
+                name == MANIFEST_NAME
+                    || get_enclosed_path(name).is_some_and(|path| selection.is_selected(&path))
+            })
+            .map(|(_, hash)| *hash)
+            .collect();
+
+        let mut total = 0;
+
+        for hash in selected_hashes {
+            let mut size = 0;
+
+            self.download_blob(object_entry, hash, &mut |progress| match progress {
+                DownloadProgress::Connected => on_progress(DownloadProgress::Connected),
+                DownloadProgress::Total(blob_size) => {
+                    size = blob_size;
+                    on_progress(DownloadProgress::Total(total + blob_size));
+                }
+                DownloadProgress::Received(received) => {
+                    on_progress(DownloadProgress::Received(total + received))
+                }
+                DownloadProgress::Done => {}
+            })
+            .await?;
+
+            total += size;
+        }
+
+        on_progress(DownloadProgress::Done);
+
+        Ok(())
+    }
+
+    /// Import a file into the Iroh store referencing it in place,
+    /// reporting the number of bytes of the file imported so far.
+    async fn import_file(
+        &self,
+        path: PathBuf,
+        mut on_progress: impl FnMut(u64),
+    ) -> Result<(Hash, Tag), PeerError> {
+        use iroh_blobs::provider::AddProgress as IrohAddProgress;
+
+        let mut import = self
+            .blobs
+            .client()
+            .add_from_path(path, true, SetTagOption::Auto, WrapOption::NoWrap)
+            .await?;
+
+        while let Some(event) = import.next().await {
+            match event? {
+                IrohAddProgress::Progress { offset, .. } => on_progress(offset),
+
+                IrohAddProgress::AllDone { hash, tag, .. } => return Ok((hash, tag)),
+
+                IrohAddProgress::Abort(err) => return Err(anyhow::Error::from(err).into()),
+
+                IrohAddProgress::Found { .. } | IrohAddProgress::Done { .. } => {}
+            }
+        }
+
+        Err(anyhow::anyhow!("The import ended unexpectedly").into())
+    }
+}
+
+/// Create the folders and symbolic links of the manifest of a collection that has been exported into the output folder,
+/// and restore the metadata of its files and folders, without going through symbolic links.
+fn restore_manifest_entries<'a>(
+    output_path: &Path,
+    entries: impl Iterator<Item = (PathBuf, &'a ManifestEntry)>,
+) -> Result<(), PeerError> {
+    let entries: Vec<_> = entries.collect();
+
+    for (path, entry) in &entries {
+        match &entry.kind {
+            ManifestEntryKind::Folder => create_dir_all(output_path, path)?,
+            ManifestEntryKind::Symlink { target } => create_symlink(output_path, target, path)?,
+            ManifestEntryKind::File => {}
+        }
+    }
+
+    // In reverse, so the folders are restored after their content
+    for (path, entry) in entries.iter().rev() {
+        match entry.kind {
+            ManifestEntryKind::File | ManifestEntryKind::Folder => {
+                if let Some(metadata) = &entry.metadata {
+                    metadata.restore(output_path, path)?;
+                }
+            }
+            ManifestEntryKind::Symlink { .. } => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve the conflicts of the items at the root of the output folder of a collection,
+/// from the relative paths of its files and folders, and whether they are folders.
+///
+/// Returns the output path of each item, or [None] if it must be skipped.
+/// The overwritten items are removed right away.
+fn resolve_item_conflicts<'a>(
+    output_path: &Path,
+    relative_paths: impl Iterator<Item = (&'a Path, bool)>,
+    on_conflict: ConflictPolicy,
+) -> Result<HashMap<OsString, Option<PathBuf>>, PeerError> {
+    let mut items: BTreeMap<&OsStr, bool> = BTreeMap::new();
+
+    for (relative_path, is_folder) in relative_paths {
+        let mut components = relative_path.components();
+
+        if let Some(item_name) = components.next() {
+            let is_item_folder = is_folder || components.next().is_some();
+            *items.entry(item_name.as_os_str()).or_default() |= is_item_folder;
+        }
+    }
+
+    items
+        .into_iter()
+        .map(|(item_name, is_folder)| {
+            let item_path =
+                resolve_output_conflict(&output_path.join(item_name), is_folder, on_conflict)?;
+
+            if on_conflict == ConflictPolicy::Overwrite {
+                if let Some(item_path) = &item_path {
+                    remove_output(item_path)?;
+                }
+            }
+
+            Ok((item_name.to_owned(), item_path))
+        })
+        .collect()
+}
+
+/// Get the name of a file inside a collection from the name of its item and its path relative to the item,
+/// using `/` as separator in every platform.
+pub(super) fn get_collection_name(
+    item_name: &str,
+    relative_path: &Path,
+) -> Result<String, PeerError> {
+    let components = relative_path
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()
+        .ok_or(PeerError::NotUnicodePath)?;
+
+    Ok(std::iter::once(item_name)
+        .chain(components)
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+/// Get the relative path of a file inside a collection from its name,
+/// or [None] if it would be placed outside of the folder.
+fn get_enclosed_path(name: &str) -> Option<PathBuf> {
+    if name.split('/').any(str::is_empty) {
+        return None;
+    }
+
+    let path: PathBuf = name.split('/').collect();
+
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_collection_name_of_a_folder_file() {
+        assert_eq!(
+            get_collection_name("", Path::new("sub/file.txt")).ok(),
+            Some("sub/file.txt".to_string())
+        )
+    }
+
+    #[test]
+    fn test_get_collection_name_of_a_bundle_item() {
+        assert_eq!(
+            get_collection_name("item", Path::new("")).ok(),
+            Some("item".to_string())
+        )
+    }
+
+    #[test]
+    fn test_get_enclosed_path_nested() {
+        assert_eq!(
+            get_enclosed_path("item/sub/file.txt"),
+            Some(["item", "sub", "file.txt"].iter().collect())
+        )
+    }
+
+    #[test]
+    fn test_get_enclosed_path_outside() {
+        assert_eq!(get_enclosed_path("item/../../file.txt"), None)
+    }
+
+    #[test]
+    fn test_get_enclosed_path_absolute() {
+        assert_eq!(get_enclosed_path("/file.txt"), None)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_manifest_entries_cant_escape_the_output_folder() -> Result<(), PeerError> {
+        let temporal_dir = tempfile::tempdir()?;
+        let output_path = temporal_dir.path().join("output");
+        std::fs::create_dir_all(&output_path)?;
+
+        let entries = [
+            ManifestEntry {
+                name: String::from("s1"),
+                kind: ManifestEntryKind::Symlink {
+                    target: String::from("."),
+                },
+                metadata: None,
+            },
+            ManifestEntry {
+                name: String::from("s1/planted"),
+                kind: ManifestEntryKind::Folder,
+                metadata: Some(FileMetadata::default()),
+            },
+        ];
+
+        let result = restore_manifest_entries(
+            &output_path,
+            entries
+                .iter()
+                .map(|entry| (output_path.join(&entry.name), entry)),
+        );
+
+        assert!(matches!(result, Err(PeerError::PathThroughSymlink(_))));
+        assert!(!output_path.join("planted").exists());
+
+        Ok(())
+    }
+}
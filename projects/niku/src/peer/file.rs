@@ -8,11 +8,19 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use iroh_blobs::rpc::client::blobs::WrapOption;
-use iroh_blobs::store::{ExportFormat, ExportMode};
 use iroh_blobs::util::SetTagOption;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-use super::{Peer, PeerError};
+use super::output::{get_partial_path, move_into_place, remove_output, resolve_output_conflict};
+use super::{ConflictPolicy, Peer, PeerError};
 use crate::object::{HashWrapper, NodeAddrWrapper, ObjectEntry, ObjectKind};
+use crate::progress::{ExportProgress, PackProgress};
+
+/// Number of bytes read at once from a stream imported into, or exported from, the Iroh store.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Number of chunks of a stream that can be waiting to be imported into the Iroh store.
+const STREAM_CHANNEL_CAPACITY: usize = 16;
 
 impl Peer {
     /// Creates a new object entry for a file.
@@ -45,10 +53,81 @@ impl Peer {
             kind: ObjectKind::File,
             name: file_name,
             size: blob.size,
+            folder_format: None,
+            contents: None,
+            requires_password: false,
+        })
+    }
+
+    /// Creates a new object entry for a file with the given name, with the content read from a stream.
+    ///
+    /// The total size of the stream is unknown beforehand,
+    /// so only [PackProgress::Packed] and [PackProgress::Done] are reported.
+    pub async fn create_stream_object_entry(
+        &mut self,
+        name: String,
+        mut reader: impl AsyncRead + Unpin,
+        mut on_progress: impl FnMut(PackProgress),
+    ) -> Result<ObjectEntry, PeerError> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(STREAM_CHANNEL_CAPACITY);
+
+        let chunks = Box::pin(futures_lite::stream::unfold(
+            receiver,
+            |mut receiver| async move { receiver.recv().await.map(|chunk| (chunk, receiver)) },
+        ));
+
+        let add_progress = self
+            .blobs
+            .client()
+            .add_stream(chunks, SetTagOption::Auto)
+            .await?;
+
+        let mut packed_bytes = 0;
+
+        loop {
+            let mut chunk = Vec::with_capacity(STREAM_CHUNK_SIZE);
+
+            let read_bytes = reader.read_buf(&mut chunk).await?;
+
+            if read_bytes == 0 {
+                break;
+            }
+
+            // The import was aborted by the store, its error is returned when finishing it
+            if sender.send(Ok(chunk.into())).await.is_err() {
+                break;
+            }
+
+            packed_bytes += read_bytes as u64;
+            on_progress(PackProgress::Packed(packed_bytes));
+        }
+
+        // Closing the channel ends the stream
+        drop(sender);
+
+        let blob = add_progress.finish().await?;
+
+        on_progress(PackProgress::Done);
+
+        Ok(ObjectEntry {
+            node_address: NodeAddrWrapper(self.router.endpoint().node_addr().await?),
+            file_hash: HashWrapper(blob.hash),
+            kind: ObjectKind::File,
+            name,
+            size: blob.size,
+            folder_format: None,
+            contents: None,
+            requires_password: false,
         })
     }
 
-    /// Export a previously downloaded file object entry.
+    /// Export a previously downloaded file object entry,
+    /// resolving the conflict with an existing file with the given policy.
+    ///
+    /// The file is exported into a hidden partial path, that is moved into place once it's complete,
+    /// or removed if the export fails.
+    ///
+    /// Returns the path of the exported file, or [None] if it was skipped.
     ///
     /// # Safety
     /// Doesn't check neither if the given object is for a file
@@ -57,28 +136,70 @@ impl Peer {
         &self,
         object_entry: &ObjectEntry,
         custom_output_path: &Option<PathBuf>,
-    ) -> Result<PathBuf, PeerError> {
-        let output_path = if let Some(custom_output_path) = custom_output_path {
-            custom_output_path.clone()
-        } else {
-            let mut cwd_path = std::env::current_dir()?;
-            cwd_path.push(&object_entry.name);
-
-            cwd_path
+        on_conflict: ConflictPolicy,
+        mut on_progress: impl FnMut(ExportProgress),
+    ) -> Result<Option<PathBuf>, PeerError> {
+        let output_path = Peer::get_output_path(object_entry, custom_output_path)?;
+        let Some(output_path) = resolve_output_conflict(&output_path, false, on_conflict)? else {
+            on_progress(ExportProgress::Done);
+
+            return Ok(None);
         };
 
-        self.blobs
-            .client()
-            .export(
-                object_entry.file_hash.0,
-                output_path.to_owned(),
-                ExportFormat::Blob,
-                ExportMode::Copy,
-            )
-            .await?
-            .finish()
-            .await?;
+        let partial_path = get_partial_path(&output_path);
+
+        if let Err(err) = self
+            .export_blob(object_entry.file_hash.0, &partial_path, &mut on_progress)
+            .await
+        {
+            remove_output(&partial_path)?;
+
+            return Err(err);
+        }
+
+        move_into_place(&partial_path, &output_path)?;
+
+        on_progress(ExportProgress::Done);
+
+        Ok(Some(output_path))
+    }
+
+    /// Write the content of a previously downloaded file object entry into a stream,
+    /// like the standard output.
+    ///
+    /// # Safety
+    /// Doesn't check neither if the given object is for a file
+    /// or if the object has been downloaded beforehand into the Iroh store.
+    pub async unsafe fn write_file_object_entry(
+        &self,
+        object_entry: &ObjectEntry,
+        mut writer: impl AsyncWrite + Unpin,
+        mut on_progress: impl FnMut(ExportProgress),
+    ) -> Result<(), PeerError> {
+        let mut reader = self.blobs.client().read(object_entry.file_hash.0).await?;
+
+        on_progress(ExportProgress::Total(reader.size()));
+
+        let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+        let mut exported_bytes = 0;
+
+        loop {
+            let read_bytes = reader.read(&mut buffer).await?;
+
+            if read_bytes == 0 {
+                break;
+            }
+
+            writer.write_all(&buffer[..read_bytes]).await?;
+
+            exported_bytes += read_bytes as u64;
+            on_progress(ExportProgress::Exported(exported_bytes));
+        }
+
+        writer.flush().await?;
+
+        on_progress(ExportProgress::Done);
 
-        Ok(output_path.to_owned())
+        Ok(())
     }
 }
@@ -4,140 +4,169 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+mod tar_archive;
+mod zip_archive;
+
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
 
 use anyhow::Result;
-use chrono::{DateTime, Utc};
-use iroh_blobs::rpc::client::blobs::WrapOption;
-use iroh_blobs::store::{ExportFormat, ExportMode};
-use iroh_blobs::util::SetTagOption;
-use tokio::fs;
-use walkdir::WalkDir;
-use zip::write::SimpleFileOptions;
-use zip::CompressionMethod;
-
-use super::{Peer, PeerError};
-use crate::object::{HashWrapper, NodeAddrWrapper, ObjectEntry, ObjectKind};
 
-impl Peer {
-    async fn create_temporal_zip_file(subfolder_name: &str) -> Result<PathBuf, PeerError> {
-        let now: DateTime<Utc> = SystemTime::now().into();
+use super::output::{get_partial_path, move_into_place, remove_output, resolve_output_conflict};
+use super::{
+    Compression, ConflictPolicy, EntrySelection, ExtractLimits, PackOptions, Peer, PeerError,
+};
+use crate::object::{FolderFormat, HashWrapper, NodeAddrWrapper, ObjectEntry, ObjectKind};
+use crate::progress::{ExportProgress, PackProgress};
+
+/// Reader that reports the number of bytes read so far after each read.
+struct ProgressReader<R, F> {
+    inner: R,
+    read_bytes: u64,
+    on_read: F,
+}
 
-        #[allow(clippy::expect_used)]
-        let mut temporal_zip_path = crate::get_cache_path();
+impl<R: Read, F: FnMut(u64)> ProgressReader<R, F> {
+    fn new(inner: R, on_read: F) -> ProgressReader<R, F> {
+        ProgressReader {
+            inner,
+            read_bytes: 0,
+            on_read,
+        }
+    }
+}
 
-        temporal_zip_path.push(format!("{subfolder_name}/{}.zip", now.format("%+")));
+impl<R: Read, F: FnMut(u64)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
 
-        fs::create_dir_all(temporal_zip_path.parent().ok_or(PeerError::FolderIsRoot)?).await?;
+        self.read_bytes += read as u64;
+        (self.on_read)(self.read_bytes);
 
-        Ok(temporal_zip_path)
+        Ok(read)
     }
+}
 
-    fn compress_a_directory(src_path: &Path, zip_file: &File) -> Result<(), PeerError> {
-        let walkdir = WalkDir::new(src_path)
-            .into_iter()
-            .filter_map(|path| path.ok());
-
-        let mut zip = zip::ZipWriter::new(zip_file);
-        let options = SimpleFileOptions::default()
-            .compression_method(CompressionMethod::Deflated)
-            .unix_permissions(0o755);
-
-        let mut buffer = Vec::new();
-        for entry in walkdir {
-            let path = entry.path();
-            let name = path.strip_prefix(src_path)?;
-            let path_as_string = name
-                .to_str()
-                .map(str::to_owned)
-                .ok_or(PeerError::NotUnicodePath)?;
-
-            // Write file or directory explicitly
-            // Some unzip tools unzip files with directory paths correctly, some do not!
-            if path.is_file() {
-                zip.start_file(path_as_string, options)?;
-                let mut file = File::open(path)?;
-
-                file.read_to_end(&mut buffer)?;
-                zip.write_all(&buffer)?;
-                buffer.clear();
-            } else if !name.as_os_str().is_empty() {
-                // Only if not root! Avoids path spec / warning
-                // and mapname conversion failed error on unzip
-                //
-                zip.add_directory(path_as_string, options)?;
-            }
+/// Counter of the entries and bytes extracted from an archive, that fails once they exceed the limits.
+struct ExtractLimiter {
+    max_bytes: u64,
+    max_entries: u64,
+    extracted_bytes: u64,
+    extracted_entries: u64,
+}
+
+impl ExtractLimiter {
+    fn new(limits: &ExtractLimits, archive_size: u64) -> ExtractLimiter {
+        let max_bytes = archive_size.saturating_mul(limits.max_ratio);
+
+        ExtractLimiter {
+            max_bytes: limits
+                .max_bytes
+                .map_or(max_bytes, |limit| limit.min(max_bytes)),
+            max_entries: limits.max_entries,
+            extracted_bytes: 0,
+            extracted_entries: 0,
         }
+    }
+
+    fn add_entry(&mut self) -> Result<(), PeerError> {
+        self.extracted_entries += 1;
 
-        zip.finish()?;
+        if self.extracted_entries > self.max_entries {
+            return Err(PeerError::ExtractLimitExceeded(format!(
+                "more than {} files, folders and links",
+                self.max_entries
+            )));
+        }
 
         Ok(())
     }
 
-    fn decompress_a_directory(
-        zip_file_path: &Path,
-        destination_path: &Path,
-    ) -> Result<(), PeerError> {
-        let file = std::fs::File::open(zip_file_path)?;
-        let mut archive = zip::ZipArchive::new(file)?;
-
-        for i in 0..archive.len() {
-            #[allow(clippy::expect_used)]
-            let mut file = archive
-                .by_index(i)
-                .expect("The file should always have an index");
-
-            let destination_path = destination_path.join(match file.enclosed_name() {
-                Some(path) => path,
-                None => continue,
-            });
-
-            if file.is_dir() {
-                std::fs::create_dir_all(&destination_path)?;
-            } else {
-                if let Some(p) = destination_path.parent() {
-                    if !p.exists() {
-                        std::fs::create_dir_all(p)?;
-                    }
-                }
-                let mut outfile = std::fs::File::create(&destination_path)?;
-                std::io::copy(&mut file, &mut outfile)?;
-            }
+    /// Copy the data of an entry, failing as soon as the limit of bytes is exceeded.
+    fn copy(&mut self, reader: &mut impl Read, writer: &mut impl Write) -> Result<(), PeerError> {
+        let remaining_bytes = self.max_bytes - self.extracted_bytes;
+
+        self.extracted_bytes +=
+            io::copy(&mut reader.take(remaining_bytes.saturating_add(1)), writer)?;
+
+        if self.extracted_bytes > self.max_bytes {
+            return Err(PeerError::ExtractLimitExceeded(format!(
+                "more than {} of data",
+                crate::format_bytes_with_unit(self.max_bytes)
+            )));
         }
 
         Ok(())
     }
+}
+
+/// Get the compression of the files of a folder transferred with the given format,
+/// the default one of the format if there is no compression.
+fn get_folder_compression(
+    format: FolderFormat,
+    compression: Option<Compression>,
+) -> Result<Compression, PeerError> {
+    match (format, compression) {
+        (FolderFormat::Collection | FolderFormat::Tar, None) => Ok(Compression::Store),
+        (FolderFormat::Zip, None) => Ok(Compression::Deflate(None)),
+        (FolderFormat::TarZst, None) => Ok(Compression::Zstd(None)),
+
+        (FolderFormat::Zip, Some(compression))
+        | (FolderFormat::Collection | FolderFormat::Tar, Some(compression @ Compression::Store))
+        | (FolderFormat::TarZst, Some(compression @ Compression::Zstd(_))) => Ok(compression),
+
+        (_, Some(compression)) => Err(PeerError::UnsupportedCompression {
+            format,
+            compression,
+        }),
+    }
+}
 
-    /// Creates a new object entry for a file.
+impl Peer {
+    /// Creates a new object entry for a folder, transferred with the given format.
+    ///
+    /// May return the path of a temporal file that must be removed once the object is no longer sent.
     ///
     /// # Safety
     /// Doesn't check if the given path is for a folder.
     pub async unsafe fn create_folder_object_entry(
         &mut self,
         src_path: PathBuf,
-    ) -> Result<(ObjectEntry, PathBuf), PeerError> {
-        let temporal_zip_path =
-            Peer::create_temporal_zip_file("published-compressed-folders").await?;
-
-        let temporal_zip_file = File::create(temporal_zip_path.clone())?;
-
-        Peer::compress_a_directory(&src_path, &temporal_zip_file)?;
-
-        let blob = self
-            .blobs
-            .client()
-            .add_from_path(
-                temporal_zip_path.clone(),
-                true,
-                SetTagOption::Auto,
-                WrapOption::NoWrap,
-            )
-            .await?
-            .finish()
-            .await?;
+        format: FolderFormat,
+        options: &PackOptions,
+        mut on_progress: impl FnMut(PackProgress),
+    ) -> Result<(ObjectEntry, Option<PathBuf>), PeerError> {
+        let compression = get_folder_compression(format, options.compression)?;
+
+        let (hash, size, temporal_file_path) = match format {
+            FolderFormat::Collection => {
+                let (hash, size) = self
+                    .create_collection(&[("", &src_path)], options, &mut on_progress)
+                    .await?;
+
+                (hash, size, None)
+            }
+
+            FolderFormat::Zip => {
+                let (hash, size, temporal_zip_path) = self
+                    .create_folder_zip(&src_path, compression, options, &mut on_progress)
+                    .await?;
+
+                (hash, size, Some(temporal_zip_path))
+            }
+
+            FolderFormat::Tar | FolderFormat::TarZst => {
+                let (hash, size, temporal_tar_path) = self
+                    .create_folder_tar(&src_path, compression, options, &mut on_progress)
+                    .await?;
+
+                (hash, size, Some(temporal_tar_path))
+            }
+        };
+
+        let contents = self.create_listing(&[("", &src_path)], options).await?;
+
+        on_progress(PackProgress::Done);
 
         #[allow(clippy::expect_used)]
         let file_name = src_path
@@ -150,16 +179,28 @@ impl Peer {
         Ok((
             ObjectEntry {
                 node_address: NodeAddrWrapper(self.router.endpoint().node_addr().await?),
-                file_hash: HashWrapper(blob.hash),
+                file_hash: HashWrapper(hash),
                 kind: ObjectKind::Folder,
                 name: file_name,
-                size: blob.size,
+                size,
+                folder_format: Some(format),
+                contents: Some(contents),
+                requires_password: false,
             },
-            temporal_zip_path,
+            temporal_file_path,
         ))
     }
 
-    /// Export a previously downloaded folder object entry.
+    /// Export a previously downloaded folder object entry,
+    /// resolving the conflict with an existing folder with the given policy.
+    ///
+    /// The folder is exported into a hidden partial path, that is moved into place once it's complete,
+    /// or removed if the export fails.
+    /// The archives of the folders are extracted within the given limits, aborting the export if they are exceeded.
+    /// With a selection, only the selected files and folders are exported.
+    ///
+    /// Returns the path of the exported folder, or [None] if it was skipped,
+    /// and may return the path of a temporal file that must be removed.
     ///
     /// # Safety
     /// Doesn't check neither if the given object is for a folder
@@ -168,33 +209,69 @@ impl Peer {
         &self,
         object_entry: &ObjectEntry,
         custom_output_path: &Option<PathBuf>,
-    ) -> Result<(PathBuf, Option<PathBuf>), PeerError> {
-        let output_path = if let Some(custom_output_path) = custom_output_path {
-            custom_output_path.clone()
-        } else {
-            let mut cwd_path = std::env::current_dir()?;
-            cwd_path.push(&object_entry.name);
-
-            cwd_path
+        on_conflict: ConflictPolicy,
+        limits: &ExtractLimits,
+        selection: Option<&EntrySelection>,
+        mut on_progress: impl FnMut(ExportProgress),
+    ) -> Result<Option<(PathBuf, Option<PathBuf>)>, PeerError> {
+        let output_path = Peer::get_output_path(object_entry, custom_output_path)?;
+
+        let Some(output_path) = resolve_output_conflict(&output_path, true, on_conflict)? else {
+            on_progress(ExportProgress::Done);
+
+            return Ok(None);
         };
 
-        let temporal_zip_path =
-            Peer::create_temporal_zip_file("downloaded-compressed-folders").await?;
+        let partial_path = get_partial_path(&output_path);
+
+        let result = match object_entry.folder_format {
+            Some(FolderFormat::Collection) => self
+                .export_collection(
+                    object_entry,
+                    &partial_path,
+                    on_conflict,
+                    selection,
+                    &mut on_progress,
+                )
+                .await
+                .map(|_| None),
+
+            Some(FolderFormat::Zip) | None => self
+                .export_folder_zip(
+                    object_entry,
+                    &partial_path,
+                    limits,
+                    selection,
+                    &mut on_progress,
+                )
+                .await
+                .map(Some),
+
+            Some(FolderFormat::Tar | FolderFormat::TarZst) => self
+                .export_folder_tar(
+                    object_entry,
+                    &partial_path,
+                    limits,
+                    selection,
+                    &mut on_progress,
+                )
+                .await
+                .map(Some),
+        };
+
+        let temporal_file_path = match result {
+            Ok(temporal_file_path) => temporal_file_path,
+            Err(err) => {
+                remove_output(&partial_path)?;
+
+                return Err(err);
+            }
+        };
 
-        self.blobs
-            .client()
-            .export(
-                object_entry.file_hash.0,
-                temporal_zip_path.to_owned(),
-                ExportFormat::Blob,
-                ExportMode::Copy,
-            )
-            .await?
-            .finish()
-            .await?;
+        move_into_place(&partial_path, &output_path)?;
 
-        Peer::decompress_a_directory(&temporal_zip_path, &output_path)?;
+        on_progress(ExportProgress::Done);
 
-        Ok((output_path.to_owned(), Some(temporal_zip_path)))
+        Ok(Some((output_path, temporal_file_path)))
     }
 }
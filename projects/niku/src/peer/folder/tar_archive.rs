@@ -0,0 +1,392 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use iroh_blobs::rpc::client::blobs::WrapOption;
+use iroh_blobs::util::SetTagOption;
+use iroh_blobs::Hash;
+use tar::{EntryType, Header, HeaderMode};
+
+use super::{ExtractLimiter, ProgressReader};
+use crate::object::{FolderFormat, ObjectEntry};
+use crate::peer::metadata::{check_no_symlinks, create_dir_all, create_symlink, FileMetadata};
+use crate::peer::walk::Walk;
+use crate::peer::{Compression, EntrySelection, ExtractLimits, PackOptions, Peer, PeerError};
+use crate::progress::{ExportProgress, PackProgress};
+
+impl Peer {
+    /// Write the files of a folder as a tar archive into the given writer.
+    fn archive_a_directory(
+        src_path: &Path,
+        writer: impl Write,
+        options: &PackOptions,
+        on_progress: &mut impl FnMut(PackProgress),
+    ) -> Result<(), PeerError> {
+        let walk = Walk::new(src_path, options)?;
+
+        on_progress(PackProgress::Skipped {
+            entries: walk.skipped_entries,
+            bytes: walk.skipped_bytes,
+        });
+
+        on_progress(PackProgress::Total(walk.file_bytes()));
+
+        let mut packed_bytes = 0;
+
+        let mut builder = tar::Builder::new(writer);
+
+        for entry in walk.entries {
+            let name = &entry.relative_path;
+
+            // The root is the output folder
+            if name.as_os_str().is_empty() {
+                continue;
+            }
+
+            let mut header = Header::new_gnu();
+
+            if options.preserve_metadata {
+                header.set_metadata_in_mode(&entry.metadata, HeaderMode::Complete);
+            } else {
+                // A modification time of zero marks it as not preserved
+                header.set_metadata_in_mode(&entry.metadata, HeaderMode::Deterministic);
+                header.set_mtime(0);
+            }
+
+            if entry.file_type.is_symlink() {
+                let target = std::fs::read_link(&entry.path)?;
+
+                builder.append_link(&mut header, name, target)?;
+            } else if entry.file_type.is_file() {
+                let path_as_string = name.to_str().ok_or(PeerError::NotUnicodePath)?;
+                on_progress(PackProgress::File(path_as_string.to_owned()));
+
+                let file = ProgressReader::new(File::open(&entry.path)?, |read_bytes| {
+                    on_progress(PackProgress::Packed(packed_bytes + read_bytes))
+                });
+
+                builder.append_data(&mut header, name, file)?;
+                packed_bytes += entry.metadata.len();
+            } else if entry.file_type.is_dir() {
+                builder.append_data(&mut header, name, io::empty())?;
+            }
+        }
+
+        builder.into_inner()?.flush()?;
+
+        Ok(())
+    }
+
+    /// Extract the files of a tar archive read from the given reader into a folder,
+    /// only the selected entries if there is a selection.
+    fn extract_a_directory(
+        reader: impl Read,
+        destination: &Path,
+        limiter: &mut ExtractLimiter,
+        selection: Option<&EntrySelection>,
+    ) -> Result<(), PeerError> {
+        let mut archive = tar::Archive::new(reader);
+
+        // The symbolic links are created at the end, so no file is extracted through them
+        let mut symlinks = Vec::new();
+
+        let mut extracted_metadata = Vec::new();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+
+            let path = entry.path()?.into_owned();
+
+            if path.as_os_str().is_empty()
+                || !path
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+                || selection.is_some_and(|selection| !selection.is_selected(&path))
+            {
+                continue;
+            }
+
+            let destination_path = destination.join(path);
+
+            limiter.add_entry()?;
+
+            let header = entry.header();
+            let file_metadata = FileMetadata {
+                mode: header.mode().ok().map(|mode| mode & 0o777),
+                modified: header
+                    .mtime()
+                    .ok()
+                    .filter(|mtime| *mtime > 0)
+                    .map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime)),
+            };
+
+            match header.entry_type() {
+                EntryType::Symlink => {
+                    let target = entry.link_name()?.unwrap_or_default();
+                    let target = target.to_str().ok_or(PeerError::NotUnicodePath)?;
+
+                    symlinks.push((target.to_owned(), destination_path));
+                    continue;
+                }
+
+                EntryType::Directory => create_dir_all(destination, &destination_path)?,
+
+                EntryType::Regular | EntryType::Continuous => {
+                    check_no_symlinks(destination, &destination_path)?;
+
+                    if let Some(parent) = destination_path.parent() {
+                        create_dir_all(destination, parent)?;
+                    }
+
+                    limiter.copy(&mut entry, &mut File::create(&destination_path)?)?;
+                }
+
+                _ => continue,
+            }
+
+            extracted_metadata.push((destination_path, file_metadata));
+        }
+
+        if selection.is_some() && limiter.extracted_entries == 0 {
+            return Err(PeerError::NothingSelected);
+        }
+
+        for (target, path) in symlinks {
+            create_symlink(destination, &target, &path)?;
+        }
+
+        // In reverse, so the folders are restored after their content
+        for (path, file_metadata) in extracted_metadata.iter().rev() {
+            file_metadata.restore(destination, path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Archive a folder with the given compression into a temporal tar file and add it to the Iroh store.
+    ///
+    /// Only [Compression::Store] and [Compression::Zstd] are supported.
+    ///
+    /// Returns the hash and size of the tar file, and its path, that must be removed afterwards.
+    pub(super) async fn create_folder_tar(
+        &self,
+        src_path: &Path,
+        compression: Compression,
+        options: &PackOptions,
+        on_progress: &mut impl FnMut(PackProgress),
+    ) -> Result<(Hash, u64, PathBuf), PeerError> {
+        let suffix = match compression {
+            Compression::Store => ".tar",
+            Compression::Zstd(_) => ".tar.zst",
+            Compression::Deflate(_) => {
+                return Err(PeerError::UnsupportedCompression {
+                    format: FolderFormat::Tar,
+                    compression,
+                })
+            }
+        };
+
+        let temporal_tar_path =
+            Peer::create_temporal_path("published-archived-folders", suffix).await?;
+
+        let temporal_tar_file = File::create(&temporal_tar_path)?;
+
+        if let Compression::Zstd(level) = compression {
+            let mut encoder = zstd::Encoder::new(
+                temporal_tar_file,
+                level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL),
+            )?;
+
+            Peer::archive_a_directory(src_path, &mut encoder, options, on_progress)?;
+            encoder.finish()?;
+        } else {
+            Peer::archive_a_directory(src_path, temporal_tar_file, options, on_progress)?;
+        }
+
+        let blob = self
+            .blobs
+            .client()
+            .add_from_path(
+                temporal_tar_path.clone(),
+                true,
+                SetTagOption::Auto,
+                WrapOption::NoWrap,
+            )
+            .await?
+            .finish()
+            .await?;
+
+        Ok((blob.hash, blob.size, temporal_tar_path))
+    }
+
+    /// Export the tar file of a folder object entry into a temporal file and extract it within the limits,
+    /// only the selected entries if there is a selection.
+    ///
+    /// Returns the path of the temporal tar file, that must be removed afterwards.
+    pub(super) async fn export_folder_tar(
+        &self,
+        object_entry: &ObjectEntry,
+        output_path: &Path,
+        limits: &ExtractLimits,
+        selection: Option<&EntrySelection>,
+        on_progress: &mut impl FnMut(ExportProgress),
+    ) -> Result<PathBuf, PeerError> {
+        let is_compressed = object_entry.folder_format == Some(FolderFormat::TarZst);
+
+        let temporal_tar_path = Peer::create_temporal_path(
+            "downloaded-archived-folders",
+            if is_compressed { ".tar.zst" } else { ".tar" },
+        )
+        .await?;
+
+        self.export_blob(object_entry.file_hash.0, &temporal_tar_path, on_progress)
+            .await?;
+
+        let temporal_tar_file = File::open(&temporal_tar_path)?;
+
+        // The progress of the extraction is the number of bytes read from the archive
+        let temporal_tar_size = temporal_tar_file.metadata()?.len();
+        on_progress(ExportProgress::Extracting(temporal_tar_size));
+
+        let mut limiter = ExtractLimiter::new(limits, temporal_tar_size);
+
+        let reader = ProgressReader::new(temporal_tar_file, |read_bytes| {
+            on_progress(ExportProgress::Exported(read_bytes))
+        });
+
+        if is_compressed {
+            Peer::extract_a_directory(
+                zstd::Decoder::new(reader)?,
+                output_path,
+                &mut limiter,
+                selection,
+            )?;
+        } else {
+            Peer::extract_a_directory(reader, output_path, &mut limiter, selection)?;
+        }
+
+        // The padding at the end of the archive is never read
+        on_progress(ExportProgress::Exported(temporal_tar_size));
+
+        Ok(temporal_tar_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a tar archive with the given symbolic links, with their paths and targets.
+    fn make_symlinks_tar(symlinks: &[(&str, &str)]) -> Result<Vec<u8>, PeerError> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        for (path, target) in symlinks {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+
+            builder.append_link(&mut header, path, target)?;
+        }
+
+        Ok(builder.into_inner()?)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tar_symlinks_cant_escape_the_destination() -> Result<(), PeerError> {
+        let temporal_dir = tempfile::tempdir()?;
+        let outside_path = temporal_dir.path().join("outside");
+        std::fs::create_dir_all(&outside_path)?;
+
+        let outside = outside_path.to_str().ok_or(PeerError::NotUnicodePath)?;
+        let malicious_archives = [
+            make_symlinks_tar(&[("s1", outside), ("s1/planted", "x")])?,
+            make_symlinks_tar(&[("s1", "../outside"), ("s1/planted", "x")])?,
+            make_symlinks_tar(&[("s1", "."), ("s1/planted", "x")])?,
+        ];
+
+        for (i, archive) in malicious_archives.iter().enumerate() {
+            let result = Peer::extract_a_directory(
+                archive.as_slice(),
+                &temporal_dir.path().join(format!("destination-{i}")),
+                &mut ExtractLimiter::new(&ExtractLimits::default(), archive.len() as u64),
+                None,
+            );
+
+            assert!(matches!(
+                result,
+                Err(PeerError::PathThroughSymlink(_)
+                    | PeerError::SymlinkOutsideOfDestination { .. })
+            ));
+            assert!(std::fs::read_dir(&outside_path)?.next().is_none());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tar_zst_round_trip_preserves_metadata() -> Result<(), PeerError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let long_name = "a".repeat(200);
+
+        let temporal_dir = tempfile::tempdir()?;
+        let src_path = temporal_dir.path().join("src");
+        let destination_path = temporal_dir.path().join("destination");
+
+        std::fs::create_dir_all(src_path.join("bin"))?;
+        std::fs::create_dir_all(src_path.join("empty"))?;
+        std::fs::write(src_path.join("bin").join(&long_name), "long name")?;
+        std::fs::write(src_path.join("bin/run.sh"), "#!/bin/sh")?;
+        std::fs::set_permissions(
+            src_path.join("bin/run.sh"),
+            std::fs::Permissions::from_mode(0o750),
+        )?;
+        File::open(src_path.join("bin/run.sh"))?.set_modified(modified)?;
+        std::os::unix::fs::symlink("bin/run.sh", src_path.join("run"))?;
+
+        let mut archive = Vec::new();
+        let mut encoder = zstd::Encoder::new(&mut archive, 0)?;
+        Peer::archive_a_directory(
+            &src_path,
+            &mut encoder,
+            &PackOptions::default(),
+            &mut |_| {},
+        )?;
+        encoder.finish()?;
+
+        Peer::extract_a_directory(
+            zstd::Decoder::new(archive.as_slice())?,
+            &destination_path,
+            &mut ExtractLimiter::new(&ExtractLimits::default(), archive.len() as u64),
+            None,
+        )?;
+
+        assert_eq!(
+            std::fs::read_to_string(destination_path.join("bin").join(&long_name))?,
+            "long name"
+        );
+        assert!(destination_path.join("empty").is_dir());
+
+        let metadata = std::fs::metadata(destination_path.join("bin/run.sh"))?;
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o750);
+        assert_eq!(metadata.modified()?, modified);
+
+        assert_eq!(
+            std::fs::read_link(destination_path.join("run"))?,
+            Path::new("bin/run.sh")
+        );
+
+        Ok(())
+    }
+}
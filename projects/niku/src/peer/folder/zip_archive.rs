@@ -0,0 +1,647 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc};
+use iroh_blobs::rpc::client::blobs::WrapOption;
+use iroh_blobs::util::SetTagOption;
+use iroh_blobs::Hash;
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
+
+use super::{ExtractLimiter, ProgressReader};
+use crate::object::ObjectEntry;
+use crate::peer::metadata::{check_no_symlinks, create_dir_all, create_symlink, FileMetadata};
+use crate::peer::walk::Walk;
+use crate::peer::{Compression, EntrySelection, ExtractLimits, PackOptions, Peer, PeerError};
+use crate::progress::{ExportProgress, PackProgress};
+
+/// Files from this size are written with zip64 headers, that support any size,
+/// leaving margin for compressed data bigger than the original.
+const ZIP64_FILE_SIZE_THRESHOLD: u64 = 0xF000_0000;
+
+impl Peer {
+    async fn create_temporal_zip_file(subfolder_name: &str) -> Result<PathBuf, PeerError> {
+        Peer::create_temporal_path(subfolder_name, ".zip").await
+    }
+
+    fn compress_a_directory(
+        src_path: &Path,
+        zip_file: &File,
+        compression: Compression,
+        options: &PackOptions,
+        on_progress: &mut impl FnMut(PackProgress),
+    ) -> Result<(), PeerError> {
+        let walk = Walk::new(src_path, options)?;
+
+        on_progress(PackProgress::Skipped {
+            entries: walk.skipped_entries,
+            bytes: walk.skipped_bytes,
+        });
+
+        on_progress(PackProgress::Total(walk.file_bytes()));
+
+        let mut packed_bytes = 0;
+
+        let mut zip = zip::ZipWriter::new(zip_file);
+
+        for entry in walk.entries {
+            let path = &entry.path;
+            let name = &entry.relative_path;
+            let path_as_string = name
+                .to_str()
+                .map(str::to_owned)
+                .ok_or(PeerError::NotUnicodePath)?;
+
+            let metadata = &entry.metadata;
+
+            // The default modification time is the current one, so the archive wouldn't be reproducible
+            let mut entry_options =
+                get_zip_file_options(compression).last_modified_time(zip::DateTime::default());
+
+            if options.preserve_metadata {
+                let file_metadata = FileMetadata::from_fs(metadata);
+
+                if let Some(mode) = file_metadata.mode {
+                    entry_options = entry_options.unix_permissions(mode);
+                }
+
+                if let Some(modified) = file_metadata.modified.and_then(get_zip_date_time) {
+                    entry_options = entry_options.last_modified_time(modified);
+                }
+            }
+
+            // Write file or directory explicitly
+            // Some unzip tools unzip files with directory paths correctly, some do not!
+            if entry.file_type.is_symlink() {
+                let target = std::fs::read_link(path)?;
+                let target = target.to_str().ok_or(PeerError::NotUnicodePath)?;
+
+                zip.add_symlink(path_as_string, target, entry_options)?;
+            } else if entry.file_type.is_file() {
+                on_progress(PackProgress::File(path_as_string.clone()));
+                let is_large_file = metadata.len() >= ZIP64_FILE_SIZE_THRESHOLD;
+                zip.start_file(path_as_string, entry_options.large_file(is_large_file))?;
+
+                let mut file = ProgressReader::new(File::open(path)?, |read_bytes| {
+                    on_progress(PackProgress::Packed(packed_bytes + read_bytes))
+                });
+
+                packed_bytes += io::copy(&mut file, &mut zip)?;
+            } else if entry.file_type.is_dir() && !name.as_os_str().is_empty() {
+                // Only if not root! Avoids path spec / warning
+                // and mapname conversion failed error on unzip
+                //
+                zip.add_directory(path_as_string, entry_options)?;
+            }
+        }
+
+        zip.finish()?;
+
+        Ok(())
+    }
+
+    fn decompress_a_directory(
+        zip_file_path: &Path,
+        destination: &Path,
+        limits: &ExtractLimits,
+        selection: Option<&EntrySelection>,
+        on_progress: &mut impl FnMut(ExportProgress),
+    ) -> Result<(), PeerError> {
+        let file = std::fs::File::open(zip_file_path)?;
+        let mut limiter = ExtractLimiter::new(limits, file.metadata()?.len());
+        let mut archive = zip::ZipArchive::new(file)?;
+
+        let is_selected =
+            |path: &Path| selection.is_none_or(|selection| selection.is_selected(path));
+
+        let mut selected_size: u64 = 0;
+
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+
+            if file.enclosed_name().is_some_and(|path| is_selected(&path)) {
+                selected_size = selected_size.saturating_add(file.size());
+            }
+        }
+
+        on_progress(ExportProgress::Extracting(selected_size));
+
+        // The symbolic links are created at the end, so no file is extracted through them
+        let mut symlinks = Vec::new();
+
+        let mut extracted_metadata = Vec::new();
+
+        for i in 0..archive.len() {
+            #[allow(clippy::expect_used)]
+            let mut file = archive
+                .by_index(i)
+                .expect("The file should always have an index");
+
+            let destination_path = destination.join(match file.enclosed_name() {
+                Some(path) if is_selected(&path) => path,
+                _ => continue,
+            });
+
+            limiter.add_entry()?;
+
+            let file_metadata = FileMetadata {
+                mode: file.unix_mode().map(|mode| mode & 0o777),
+                modified: file.last_modified().and_then(get_system_time),
+            };
+
+            if file.is_symlink() {
+                let mut target = Vec::new();
+                limiter.copy(&mut file, &mut target)?;
+                let target = String::from_utf8(target).map_err(|_| PeerError::NotUnicodePath)?;
+
+                symlinks.push((target, destination_path));
+                continue;
+            }
+
+            if file.is_dir() {
+                create_dir_all(destination, &destination_path)?;
+            } else {
+                check_no_symlinks(destination, &destination_path)?;
+
+                if let Some(parent) = destination_path.parent() {
+                    create_dir_all(destination, parent)?;
+                }
+                let mut outfile = std::fs::File::create(&destination_path)?;
+                limiter.copy(&mut file, &mut outfile)?;
+
+                on_progress(ExportProgress::Exported(limiter.extracted_bytes));
+            }
+
+            extracted_metadata.push((destination_path, file_metadata));
+        }
+
+        if selection.is_some() && limiter.extracted_entries == 0 {
+            return Err(PeerError::NothingSelected);
+        }
+
+        for (target, path) in symlinks {
+            create_symlink(destination, &target, &path)?;
+        }
+
+        // In reverse, so the folders are restored after their content
+        for (path, file_metadata) in extracted_metadata.iter().rev() {
+            file_metadata.restore(destination, path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Compress a folder with the given compression into a temporal zip file and add it to the Iroh store.
+    ///
+    /// Returns the hash and size of the zip file, and its path, that must be removed afterwards.
+    pub(super) async fn create_folder_zip(
+        &self,
+        src_path: &Path,
+        compression: Compression,
+        options: &PackOptions,
+        on_progress: &mut impl FnMut(PackProgress),
+    ) -> Result<(Hash, u64, PathBuf), PeerError> {
+        let temporal_zip_path =
+            Peer::create_temporal_zip_file("published-compressed-folders").await?;
+
+        let temporal_zip_file = File::create(temporal_zip_path.clone())?;
+
+        Peer::compress_a_directory(
+            src_path,
+            &temporal_zip_file,
+            compression,
+            options,
+            on_progress,
+        )?;
+
+        let blob = self
+            .blobs
+            .client()
+            .add_from_path(
+                temporal_zip_path.clone(),
+                true,
+                SetTagOption::Auto,
+                WrapOption::NoWrap,
+            )
+            .await?
+            .finish()
+            .await?;
+
+        Ok((blob.hash, blob.size, temporal_zip_path))
+    }
+
+    /// Export the zip file of a folder object entry into a temporal file and extract it within the limits,
+    /// only the selected entries if there is a selection.
+    ///
+    /// Returns the path of the temporal zip file, that must be removed afterwards.
+    pub(super) async fn export_folder_zip(
+        &self,
+        object_entry: &ObjectEntry,
+        output_path: &Path,
+        limits: &ExtractLimits,
+        selection: Option<&EntrySelection>,
+        on_progress: &mut impl FnMut(ExportProgress),
+    ) -> Result<PathBuf, PeerError> {
+        let temporal_zip_path =
+            Peer::create_temporal_zip_file("downloaded-compressed-folders").await?;
+
+        self.export_blob(object_entry.file_hash.0, &temporal_zip_path, on_progress)
+            .await?;
+
+        Peer::decompress_a_directory(
+            &temporal_zip_path,
+            output_path,
+            limits,
+            selection,
+            on_progress,
+        )?;
+
+        Ok(temporal_zip_path)
+    }
+}
+
+/// Get the options of the files of a zip archive with the given compression.
+fn get_zip_file_options(compression: Compression) -> SimpleFileOptions {
+    let (method, level) = match compression {
+        Compression::Store => (CompressionMethod::Stored, None),
+        Compression::Deflate(level) => (CompressionMethod::Deflated, level),
+        Compression::Zstd(level) => (CompressionMethod::Zstd, level),
+    };
+
+    SimpleFileOptions::default()
+        .compression_method(method)
+        .compression_level(level.map(i64::from))
+}
+
+/// Get the date and time of a zip entry, in UTC, if it fits in its range.
+fn get_zip_date_time(time: SystemTime) -> Option<zip::DateTime> {
+    let time: DateTime<Utc> = time.into();
+
+    zip::DateTime::from_date_and_time(
+        time.year().try_into().ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
+
+/// Get the time of the date and time of a zip entry, that is in UTC.
+///
+/// The default date and time, the minimum one, marks the modification time as not preserved.
+fn get_system_time(date_time: zip::DateTime) -> Option<SystemTime> {
+    if date_time == zip::DateTime::default() {
+        return None;
+    }
+
+    let time = NaiveDate::from_ymd_opt(
+        date_time.year().into(),
+        date_time.month().into(),
+        date_time.day().into(),
+    )?
+    .and_hms_opt(
+        date_time.hour().into(),
+        date_time.minute().into(),
+        date_time.second().into(),
+    )?
+    .and_utc();
+
+    Some(time.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_zip_symlinks_cant_escape_the_destination() -> Result<(), PeerError> {
+        let temporal_dir = tempfile::tempdir()?;
+        let outside_path = temporal_dir.path().join("outside");
+        std::fs::create_dir_all(&outside_path)?;
+
+        let outside = outside_path.to_str().ok_or(PeerError::NotUnicodePath)?;
+        let malicious_symlinks = [
+            [("s1", outside), ("s1/planted", "x")],
+            [("s1", "../outside"), ("s1/planted", "x")],
+            [("s1", "."), ("s1/planted", "x")],
+        ];
+
+        for (i, symlinks) in malicious_symlinks.iter().enumerate() {
+            let zip_path = temporal_dir.path().join(format!("malicious-{i}.zip"));
+
+            let mut zip = zip::ZipWriter::new(File::create(&zip_path)?);
+            for (path, target) in symlinks {
+                zip.add_symlink(*path, *target, SimpleFileOptions::default())?;
+            }
+            zip.finish()?;
+
+            let result = Peer::decompress_a_directory(
+                &zip_path,
+                &temporal_dir.path().join(format!("destination-{i}")),
+                &ExtractLimits::default(),
+                None,
+                &mut |_| {},
+            );
+
+            assert!(matches!(
+                result,
+                Err(PeerError::PathThroughSymlink(_)
+                    | PeerError::SymlinkOutsideOfDestination { .. })
+            ));
+            assert!(std::fs::read_dir(&outside_path)?.next().is_none());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip64_headers_are_extracted() -> Result<(), PeerError> {
+        let temporal_dir = tempfile::tempdir()?;
+        let zip_path = temporal_dir.path().join("folder.zip");
+        let destination_path = temporal_dir.path().join("destination");
+
+        // The same headers that the files bigger than 4 GiB have, but with a small file
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path)?);
+        zip.start_file(
+            "sub/large.bin",
+            get_zip_file_options(Compression::Deflate(None)).large_file(true),
+        )?;
+        zip.write_all(b"zip64 file")?;
+        zip.finish()?;
+
+        Peer::decompress_a_directory(
+            &zip_path,
+            &destination_path,
+            &ExtractLimits::default(),
+            None,
+            &mut |_| {},
+        )?;
+
+        assert_eq!(
+            std::fs::read_to_string(destination_path.join("sub/large.bin"))?,
+            "zip64 file"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "writes a file of more than 4 GiB, run it explicitly with `--ignored`"]
+    fn test_zip_round_trip_with_large_file() -> Result<(), PeerError> {
+        const LARGE_FILE_SIZE: u64 = 4 * 1024 * 1024 * 1024 + 1024;
+        const LARGE_FILE_TAIL: &[u8] = b"end of the large file";
+
+        let temporal_dir = tempfile::tempdir()?;
+        let src_path = temporal_dir.path().join("src");
+        let zip_path = temporal_dir.path().join("folder.zip");
+        let destination_path = temporal_dir.path().join("destination");
+
+        std::fs::create_dir_all(src_path.join("sub"))?;
+        std::fs::write(src_path.join("small.txt"), "small file")?;
+
+        // Sparse file, only the tail takes space on disk
+        let mut large_file = File::create(src_path.join("sub/large.bin"))?;
+        large_file.set_len(LARGE_FILE_SIZE)?;
+        large_file.seek(SeekFrom::End(-(LARGE_FILE_TAIL.len() as i64)))?;
+        large_file.write_all(LARGE_FILE_TAIL)?;
+        drop(large_file);
+
+        Peer::compress_a_directory(
+            &src_path,
+            &File::create(&zip_path)?,
+            Compression::Deflate(None),
+            &PackOptions::default(),
+            &mut |_| {},
+        )?;
+        Peer::decompress_a_directory(
+            &zip_path,
+            &destination_path,
+            &ExtractLimits {
+                // The sparse file is compressed to almost nothing
+                max_ratio: u64::MAX,
+                ..ExtractLimits::default()
+            },
+            None,
+            &mut |_| {},
+        )?;
+
+        assert_eq!(
+            std::fs::read_to_string(destination_path.join("small.txt"))?,
+            "small file"
+        );
+
+        let mut large_file = File::open(destination_path.join("sub/large.bin"))?;
+        assert_eq!(large_file.metadata()?.len(), LARGE_FILE_SIZE);
+
+        let mut tail = Vec::new();
+        large_file.seek(SeekFrom::End(-(LARGE_FILE_TAIL.len() as i64)))?;
+        large_file.read_to_end(&mut tail)?;
+        assert_eq!(tail, LARGE_FILE_TAIL);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip_extraction_is_aborted_when_exceeding_the_ratio() -> Result<(), PeerError> {
+        let temporal_dir = tempfile::tempdir()?;
+        let src_path = temporal_dir.path().join("src");
+        let zip_path = temporal_dir.path().join("folder.zip");
+        let destination_path = temporal_dir.path().join("destination");
+
+        // Zeros are compressed to much less than a hundredth of their size
+        std::fs::create_dir_all(&src_path)?;
+        std::fs::write(src_path.join("zeros.bin"), vec![0; 10 * 1024 * 1024])?;
+
+        Peer::compress_a_directory(
+            &src_path,
+            &File::create(&zip_path)?,
+            Compression::Deflate(None),
+            &PackOptions::default(),
+            &mut |_| {},
+        )?;
+
+        let result = Peer::decompress_a_directory(
+            &zip_path,
+            &destination_path,
+            &ExtractLimits::default(),
+            None,
+            &mut |_| {},
+        );
+        assert!(matches!(result, Err(PeerError::ExtractLimitExceeded(_))));
+
+        let extracted_size = std::fs::metadata(destination_path.join("zeros.bin"))?.len();
+        assert!(extracted_size <= std::fs::metadata(&zip_path)?.len() * 100 + 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip_of_the_same_files_has_the_same_hash() -> Result<(), PeerError> {
+        use std::time::Duration;
+
+        let temporal_dir = tempfile::tempdir()?;
+        let get_zip_hash = |src_path: &Path, options: &PackOptions| -> Result<Hash, PeerError> {
+            let zip_path = temporal_dir.path().join("folder.zip");
+
+            Peer::compress_a_directory(
+                src_path,
+                &File::create(&zip_path)?,
+                Compression::Deflate(None),
+                options,
+                &mut |_| {},
+            )?;
+
+            Ok(Hash::new(std::fs::read(zip_path)?))
+        };
+
+        let src_paths = [temporal_dir.path().join("a"), temporal_dir.path().join("b")];
+
+        for (i, src_path) in src_paths.iter().enumerate() {
+            std::fs::create_dir_all(src_path.join("sub"))?;
+            std::fs::write(src_path.join("z.txt"), "last")?;
+            std::fs::write(src_path.join("sub/a.txt"), "first")?;
+
+            File::open(src_path.join("z.txt"))?.set_modified(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000 * i as u64),
+            )?;
+        }
+
+        let preserve_metadata = PackOptions::default();
+        assert_eq!(
+            get_zip_hash(&src_paths[0], &preserve_metadata)?,
+            get_zip_hash(&src_paths[0], &preserve_metadata)?
+        );
+
+        let no_metadata = PackOptions {
+            preserve_metadata: false,
+            ..PackOptions::default()
+        };
+        assert_eq!(
+            get_zip_hash(&src_paths[0], &no_metadata)?,
+            get_zip_hash(&src_paths[1], &no_metadata)?
+        );
+
+        // Not the current time, that would change between different archives
+        let mut archive =
+            zip::ZipArchive::new(File::open(temporal_dir.path().join("folder.zip"))?)?;
+        for i in 0..archive.len() {
+            assert_eq!(
+                archive.by_index(i)?.last_modified(),
+                Some(zip::DateTime::default())
+            );
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_zip_round_trip_preserves_metadata() -> Result<(), PeerError> {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::Duration;
+
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+
+        let temporal_dir = tempfile::tempdir()?;
+        let src_path = temporal_dir.path().join("src");
+        let zip_path = temporal_dir.path().join("folder.zip");
+        let destination_path = temporal_dir.path().join("destination");
+
+        std::fs::create_dir_all(src_path.join("bin"))?;
+        std::fs::write(src_path.join("bin/run.sh"), "#!/bin/sh")?;
+        std::fs::set_permissions(
+            src_path.join("bin/run.sh"),
+            std::fs::Permissions::from_mode(0o750),
+        )?;
+        File::open(src_path.join("bin/run.sh"))?.set_modified(modified)?;
+        std::os::unix::fs::symlink("bin/run.sh", src_path.join("run"))?;
+
+        Peer::compress_a_directory(
+            &src_path,
+            &File::create(&zip_path)?,
+            Compression::Deflate(None),
+            &PackOptions::default(),
+            &mut |_| {},
+        )?;
+        Peer::decompress_a_directory(
+            &zip_path,
+            &destination_path,
+            &ExtractLimits::default(),
+            None,
+            &mut |_| {},
+        )?;
+
+        let metadata = std::fs::metadata(destination_path.join("bin/run.sh"))?;
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o750);
+        assert_eq!(metadata.modified()?, modified);
+
+        assert_eq!(
+            std::fs::read_link(destination_path.join("run"))?,
+            Path::new("bin/run.sh")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip_extraction_of_the_selected_entries() -> Result<(), PeerError> {
+        let temporal_dir = tempfile::tempdir()?;
+        let src_path = temporal_dir.path().join("src");
+        let zip_path = temporal_dir.path().join("folder.zip");
+        let destination_path = temporal_dir.path().join("destination");
+
+        std::fs::create_dir_all(src_path.join("docs/guide"))?;
+        std::fs::create_dir_all(src_path.join("src"))?;
+        std::fs::write(src_path.join("docs/guide/intro.md"), "intro")?;
+        std::fs::write(src_path.join("src/main.rs"), "fn main() {}")?;
+
+        Peer::compress_a_directory(
+            &src_path,
+            &File::create(&zip_path)?,
+            Compression::Deflate(None),
+            &PackOptions::default(),
+            &mut |_| {},
+        )?;
+
+        let selection = EntrySelection::new(&["docs".to_owned()])?;
+        Peer::decompress_a_directory(
+            &zip_path,
+            &destination_path,
+            &ExtractLimits::default(),
+            Some(&selection),
+            &mut |_| {},
+        )?;
+
+        assert_eq!(
+            std::fs::read_to_string(destination_path.join("docs/guide/intro.md"))?,
+            "intro"
+        );
+        assert!(!destination_path.join("src").exists());
+
+        let selection = EntrySelection::new(&["*.txt".to_owned()])?;
+        let result = Peer::decompress_a_directory(
+            &zip_path,
+            &temporal_dir.path().join("nothing"),
+            &ExtractLimits::default(),
+            Some(&selection),
+            &mut |_| {},
+        );
+        assert!(matches!(result, Err(PeerError::NothingSelected)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::Path;
+
+use anyhow::Result;
+use iroh_blobs::net_protocol::DownloadMode;
+use iroh_blobs::rpc::client::blobs::DownloadOptions;
+use iroh_blobs::util::SetTagOption;
+use iroh_blobs::{BlobFormat, Tag};
+
+use super::collection::get_collection_name;
+use super::walk::Walk;
+use super::{PackOptions, Peer, PeerError, MAX_LISTING_BYTES};
+use crate::object::{
+    HashWrapper, ListingEntry, ListingEntryKind, ObjectContents, ObjectEntry, ObjectListing,
+};
+
+impl Peer {
+    /// Walk the given items as they are packed and add the listing of their files to the Iroh store.
+    ///
+    /// The entries of the listing are named like in the collection of the items,
+    /// see [Peer::create_collection].
+    pub(super) async fn create_listing(
+        &self,
+        items: &[(&str, &Path)],
+        options: &PackOptions,
+    ) -> Result<ObjectContents, PeerError> {
+        let listing = list_items(items, options)?;
+
+        let file_count = listing
+            .entries
+            .iter()
+            .filter(|entry| entry.kind == ListingEntryKind::File)
+            .count() as u64;
+
+        let blob = self
+            .blobs
+            .client()
+            .add_bytes(serde_json::to_vec(&listing)?)
+            .await?;
+
+        Ok(ObjectContents {
+            file_count,
+            listing_hash: HashWrapper(blob.hash),
+            listing_size: blob.size,
+        })
+    }
+
+    /// Retrieve the listing of the files inside of an object entry from its sender,
+    /// without downloading the object.
+    ///
+    /// Returns [None] if the object has no listing, like files and texts,
+    /// otherwise the object must have been approved by its sender already, see [Peer::request_approval].
+    pub async fn retrieve_object_listing(
+        &self,
+        object_entry: &ObjectEntry,
+    ) -> Result<Option<ObjectListing>, PeerError> {
+        let Some(contents) = &object_entry.contents else {
+            return Ok(None);
+        };
+
+        if contents.listing_size > MAX_LISTING_BYTES {
+            return Err(PeerError::ListingTooLong);
+        }
+
+        self.check_approval(object_entry).await?;
+
+        let client = self.blobs.client();
+        let hash = contents.listing_hash.0;
+        let tag = Tag::from(format!("listing-{hash}"));
+
+        client
+            .download_with_opts(
+                hash,
+                DownloadOptions {
+                    format: BlobFormat::Raw,
+                    nodes: vec![object_entry.node_address.0.clone()],
+                    tag: SetTagOption::Named(tag.clone()),
+                    mode: DownloadMode::Queued,
+                },
+            )
+            .await?
+            .finish()
+            .await?;
+
+        let mut reader = client.read(hash).await?;
+
+        // The size announced by the sender may be different from the real one
+        let listing = if reader.size() > MAX_LISTING_BYTES {
+            Err(PeerError::ListingTooLong)
+        } else {
+            let listing_bytes = reader.read_to_bytes().await?;
+
+            serde_json::from_slice(&listing_bytes).map_err(PeerError::from)
+        };
+
+        // The blob is reclaimed by the garbage collection, unless another object has it too
+        client.tags().delete(tag).await?;
+
+        Ok(Some(listing?))
+    }
+}
+
+/// List the files, folders and symbolic links of the given items as they are packed.
+fn list_items(items: &[(&str, &Path)], options: &PackOptions) -> Result<ObjectListing, PeerError> {
+    let mut listing = ObjectListing::default();
+
+    for (item_name, item_path) in items {
+        for entry in Walk::new(item_path, options)?.entries {
+            let path = get_collection_name(item_name, &entry.relative_path)?;
+
+            // The root is the object itself
+            if path.is_empty() {
+                continue;
+            }
+
+            let (kind, size) = if entry.file_type.is_symlink() {
+                (ListingEntryKind::Symlink, 0)
+            } else if entry.file_type.is_dir() {
+                (ListingEntryKind::Folder, 0)
+            } else if entry.file_type.is_file() {
+                (ListingEntryKind::File, entry.metadata.len())
+            } else {
+                continue;
+            };
+
+            listing.entries.push(ListingEntry { path, kind, size });
+        }
+    }
+
+    Ok(listing)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_list_items_of_a_bundle() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+
+        fs::create_dir_all(root.join("folder/sub"))?;
+        fs::write(root.join("folder/sub/a.txt"), "1")?;
+        fs::write(root.join("folder/debug.log"), "22")?;
+        fs::write(root.join("folder/.nikuignore"), "*.log\n")?;
+        fs::write(root.join("file.bin"), "333")?;
+
+        let listing = list_items(
+            &[
+                ("folder", &root.join("folder")),
+                ("file.bin", &root.join("file.bin")),
+            ],
+            &PackOptions::default(),
+        )?;
+
+        let entries: Vec<_> = listing
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.kind, entry.size))
+            .collect();
+
+        assert_eq!(
+            entries,
+            [
+                ("folder", ListingEntryKind::Folder, 0),
+                ("folder/.nikuignore", ListingEntryKind::File, 6),
+                ("folder/sub", ListingEntryKind::Folder, 0),
+                ("folder/sub/a.txt", ListingEntryKind::File, 1),
+                ("file.bin", ListingEntryKind::File, 3),
+            ]
+        );
+
+        Ok(())
+    }
+}
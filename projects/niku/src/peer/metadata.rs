@@ -0,0 +1,190 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::fs::{File, Metadata};
+use std::io;
+use std::path::{Component, Path};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::PeerError;
+
+/// Metadata of a file or folder that is preserved when it's transferred.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct FileMetadata {
+    /// The Unix permission bits.
+    pub(crate) mode: Option<u32>,
+
+    /// The last modification time.
+    pub(crate) modified: Option<SystemTime>,
+}
+
+impl FileMetadata {
+    pub(crate) fn from_fs(metadata: &Metadata) -> FileMetadata {
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+
+            Some(metadata.permissions().mode() & 0o777)
+        };
+
+        #[cfg(not(unix))]
+        let mode = None;
+
+        FileMetadata {
+            mode,
+            modified: metadata
+                .modified()
+                .ok()
+                .filter(|modified| *modified >= UNIX_EPOCH),
+        }
+    }
+
+    /// Restore the metadata into the file or folder at the given path within the destination folder.
+    ///
+    /// Folders must be restored after their content, as adding files changes their modification time.
+    /// Symbolic links are skipped, as restoring them would change their targets instead.
+    pub(crate) fn restore(&self, destination: &Path, path: &Path) -> Result<(), PeerError> {
+        if let Some(parent) = path.parent() {
+            check_no_symlinks(destination, parent)?;
+        }
+
+        if std::fs::symlink_metadata(path)?.file_type().is_symlink() {
+            return Ok(());
+        }
+
+        // Folders can only be opened to change their modification time on Unix
+        if let Some(modified) = self.modified.filter(|_| cfg!(unix) || path.is_file()) {
+            File::open(path)?.set_modified(modified)?;
+        }
+
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Create a symbolic link at the given path within the destination folder, pointing to the given target,
+/// that must stay within the destination folder too.
+///
+/// Symbolic links are only created on Unix, as other platforms require special privileges.
+pub(crate) fn create_symlink(
+    destination: &Path,
+    target: &str,
+    path: &Path,
+) -> Result<(), PeerError> {
+    check_symlink_target(destination, target, path)?;
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(destination, parent)?;
+    }
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, path)?;
+
+    #[cfg(not(unix))]
+    log::warn!(
+        "Symbolic links are not supported, skipping '{}'",
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Create a folder and its missing parents within the destination folder, without going through symbolic links.
+pub(crate) fn create_dir_all(destination: &Path, path: &Path) -> Result<(), PeerError> {
+    check_no_symlinks(destination, path)?;
+
+    std::fs::create_dir_all(path)?;
+
+    Ok(())
+}
+
+/// Check that a path within the destination folder doesn't go through a symbolic link,
+/// neither the path itself nor any of its ancestors below the destination folder,
+/// so nothing is written outside of the destination folder.
+pub(crate) fn check_no_symlinks(destination: &Path, path: &Path) -> Result<(), PeerError> {
+    let mut current_path = destination.to_path_buf();
+
+    for component in path.strip_prefix(destination)?.components() {
+        current_path.push(component);
+
+        match std::fs::symlink_metadata(&current_path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(PeerError::PathThroughSymlink(path.to_path_buf()))
+            }
+            Ok(_) => {}
+
+            // The rest of the path doesn't exist yet
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the target of a symbolic link at the given path stays within the destination folder.
+///
+/// The target must be relative, and it can only go up with `..` at its start,
+/// so it's resolved without following any other symbolic link.
+fn check_symlink_target(destination: &Path, target: &str, path: &Path) -> Result<(), PeerError> {
+    // The depth of the folder of the symbolic link within the destination folder
+    let mut depth = path
+        .strip_prefix(destination)?
+        .components()
+        .count()
+        .saturating_sub(1);
+
+    let mut is_going_down = false;
+
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(_) => {
+                depth += 1;
+                is_going_down = true;
+            }
+            Component::CurDir => {}
+            Component::ParentDir if !is_going_down && depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(PeerError::SymlinkOutsideOfDestination {
+                    path: path.to_path_buf(),
+                    target: target.to_owned(),
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symlink_targets_stay_within_the_destination() {
+        let destination = Path::new("/destination");
+        let is_within = |target, path: &str| {
+            check_symlink_target(destination, target, &destination.join(path)).is_ok()
+        };
+
+        assert!(is_within("bin/run.sh", "run"));
+        assert!(is_within("../bin/run.sh", "docs/run"));
+        assert!(is_within("./../../a", "docs/guide/run"));
+
+        assert!(!is_within("/etc/passwd", "run"));
+        assert!(!is_within("../outside", "run"));
+        assert!(!is_within("../../outside", "docs/run"));
+        assert!(!is_within("bin/../../outside", "run"));
+    }
+}
@@ -0,0 +1,164 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{ConflictPolicy, PeerError};
+
+/// Suffix of the hidden paths where the objects are exported before being moved into place.
+const PARTIAL_PATH_SUFFIX: &str = "niku-part";
+
+/// Resolve the conflict with an existing file or folder at the output path of an export.
+///
+/// Returns the path where the export must be done, or [None] if it must be skipped.
+/// When overwriting, the existing file or folder is kept until it's replaced with [move_into_place].
+pub(crate) fn resolve_output_conflict(
+    output_path: &Path,
+    is_folder: bool,
+    policy: ConflictPolicy,
+) -> Result<Option<PathBuf>, PeerError> {
+    match std::fs::symlink_metadata(output_path) {
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some(output_path.to_owned()))
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    match policy {
+        ConflictPolicy::Overwrite => Ok(Some(output_path.to_owned())),
+
+        ConflictPolicy::Rename => Ok(Some(get_free_path(output_path, is_folder))),
+
+        ConflictPolicy::Skip => Ok(None),
+
+        ConflictPolicy::Fail => Err(PeerError::OutputAlreadyExists(output_path.to_owned())),
+    }
+}
+
+/// Remove the file, folder or symbolic link at the given path, if there is any.
+pub(crate) fn remove_output(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Get the hidden path next to the output path where an object is exported before being moved into place,
+/// so an incomplete export is never mistaken for a complete one.
+pub(crate) fn get_partial_path(output_path: &Path) -> PathBuf {
+    let name = output_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    output_path.with_file_name(format!(
+        ".{name}.{}.{PARTIAL_PATH_SUFFIX}",
+        std::process::id()
+    ))
+}
+
+/// Move a completely exported object from its partial path into its output path,
+/// replacing the existing file or folder.
+pub(crate) fn move_into_place(partial_path: &Path, output_path: &Path) -> io::Result<()> {
+    remove_output(output_path)?;
+
+    std::fs::rename(partial_path, output_path)
+}
+
+/// Get the first path that doesn't exist, adding a number to the name of the given one,
+/// before the extensions of the files, like `photo (1).tar.gz`.
+fn get_free_path(path: &Path, is_folder: bool) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    // The dot of the hidden files doesn't start an extension
+    let extension_start = name
+        .char_indices()
+        .skip(1)
+        .find(|(_, character)| *character == '.')
+        .map(|(index, _)| index)
+        .filter(|_| !is_folder)
+        .unwrap_or(name.len());
+
+    let (stem, extension) = name.split_at(extension_start);
+
+    (1..)
+        .map(|number| path.with_file_name(format!("{stem} ({number}){extension}")))
+        .find(|path| std::fs::symlink_metadata(path).is_err())
+        .unwrap_or_else(|| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_output_conflict_of_an_existing_file() -> Result<(), PeerError> {
+        let temporal_dir = tempfile::tempdir()?;
+        let path = temporal_dir.path().join("photo.tar.gz");
+        std::fs::write(&path, "existing")?;
+        std::fs::write(temporal_dir.path().join("photo (1).tar.gz"), "existing")?;
+
+        assert_eq!(
+            resolve_output_conflict(&path, false, ConflictPolicy::Rename)?,
+            Some(temporal_dir.path().join("photo (2).tar.gz"))
+        );
+        assert_eq!(
+            resolve_output_conflict(&path, false, ConflictPolicy::Skip)?,
+            None
+        );
+        assert!(matches!(
+            resolve_output_conflict(&path, false, ConflictPolicy::Fail),
+            Err(PeerError::OutputAlreadyExists(_))
+        ));
+
+        assert_eq!(
+            resolve_output_conflict(&path, false, ConflictPolicy::Overwrite)?,
+            Some(path.clone())
+        );
+        assert!(path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_output_conflict_of_an_existing_folder() -> Result<(), PeerError> {
+        let temporal_dir = tempfile::tempdir()?;
+        let path = temporal_dir.path().join("my.project");
+        std::fs::create_dir_all(path.join("sub"))?;
+
+        assert_eq!(
+            resolve_output_conflict(&path, true, ConflictPolicy::Rename)?,
+            Some(temporal_dir.path().join("my.project (1)"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_into_place_replaces_the_existing_folder() -> Result<(), PeerError> {
+        let temporal_dir = tempfile::tempdir()?;
+        let path = temporal_dir.path().join("folder");
+        let partial_path = get_partial_path(&path);
+
+        std::fs::create_dir_all(path.join("old"))?;
+        std::fs::create_dir_all(partial_path.join("new"))?;
+
+        move_into_place(&partial_path, &path)?;
+
+        assert!(path.join("new").is_dir());
+        assert!(!path.join("old").exists());
+        assert!(!partial_path.exists());
+
+        Ok(())
+    }
+}
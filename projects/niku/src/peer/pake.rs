@@ -0,0 +1,341 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::{anyhow, Result};
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::Scalar;
+use hmac::{Hmac, Mac};
+use iroh::NodeId;
+use serde::Serialize;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::object::{
+    sanitize_object_name, FolderFormat, HashWrapper, ObjectCode, ObjectContents, ObjectEntry,
+    ObjectKind,
+};
+
+/// Context of the SPAKE2 exchanges, so their keys can't be used for anything else.
+const PAKE_CONTEXT: &[u8] = b"niku/pake/0";
+
+/// Seed of the point `M` of SPAKE2, used by the receivers.
+const RECEIVER_POINT_SEED: &[u8] = b"niku/pake/0/M";
+
+/// Seed of the point `N` of SPAKE2, used by the senders.
+const SENDER_POINT_SEED: &[u8] = b"niku/pake/0/N";
+
+/// Number of bytes of the messages and confirmations of the exchange.
+pub(crate) const PAKE_MESSAGE_BYTES: usize = 32;
+
+/// Side of a peer in the exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Side {
+    /// The peer that sends the object.
+    Sender,
+
+    /// The peer that receives the object.
+    Receiver,
+}
+
+impl Side {
+    /// The side of the other peer of the exchange.
+    fn other(self) -> Side {
+        match self {
+            Side::Sender => Side::Receiver,
+            Side::Receiver => Side::Sender,
+        }
+    }
+}
+
+/// The description of an object that is authenticated, everything but the address of its sender,
+/// whose node is authenticated by the connection itself.
+#[derive(Serialize)]
+struct ObjectDescription<'a> {
+    file_hash: &'a HashWrapper,
+    name: String,
+    kind: &'a ObjectKind,
+    size: u64,
+    folder_format: Option<FolderFormat>,
+    contents: &'a Option<ObjectContents>,
+    requires_password: bool,
+}
+
+/// The secret shared by the sender of an object and its receivers, derived from the code of the object,
+/// with the digest of the description of the object that both of them must agree on.
+#[derive(Debug, Clone)]
+pub(crate) struct ObjectSecret {
+    password: Scalar,
+    digest: [u8; 32],
+}
+
+impl ObjectSecret {
+    /// Derive the secret of an object from its code and its entry.
+    pub(crate) fn new(code: &ObjectCode, object_entry: &ObjectEntry) -> Result<ObjectSecret> {
+        let password = Sha512::new()
+            .chain_update(PAKE_CONTEXT)
+            .chain_update(code.id.as_bytes())
+            .chain_update([0])
+            .chain_update(code.secret.as_bytes())
+            .finalize();
+
+        Ok(ObjectSecret {
+            password: Scalar::from_bytes_mod_order_wide(&password.into()),
+            digest: get_object_digest(object_entry)?,
+        })
+    }
+
+    /// The digest of the description of the object.
+    pub(crate) fn digest(&self) -> [u8; 32] {
+        self.digest
+    }
+}
+
+/// Get the digest of the description of an object.
+///
+/// The name is sanitized as the receivers do, so the senders and receivers get the same digest.
+pub(crate) fn get_object_digest(object_entry: &ObjectEntry) -> Result<[u8; 32]> {
+    let description = ObjectDescription {
+        file_hash: &object_entry.file_hash,
+        name: sanitize_object_name(&object_entry.name),
+        kind: &object_entry.kind,
+        size: object_entry.size,
+        folder_format: object_entry.folder_format,
+        contents: &object_entry.contents,
+        requires_password: object_entry.requires_password,
+    };
+
+    Ok(Sha256::digest(serde_json::to_vec(&description)?).into())
+}
+
+/// One side of a SPAKE2 exchange, until the message of the other side is received.
+pub(crate) struct Pake {
+    side: Side,
+    secret: ObjectSecret,
+    scalar: Scalar,
+    message: RistrettoPoint,
+}
+
+impl Pake {
+    /// Start the exchange on the given side, with a new random scalar.
+    pub(crate) fn start(side: Side, secret: ObjectSecret) -> Pake {
+        let scalar = Scalar::from_bytes_mod_order_wide(&rand::random());
+        let message = scalar * RISTRETTO_BASEPOINT_POINT + secret.password * get_side_point(side);
+
+        Pake {
+            side,
+            secret,
+            scalar,
+            message,
+        }
+    }
+
+    /// The message that must be sent to the other side.
+    pub(crate) fn message(&self) -> [u8; PAKE_MESSAGE_BYTES] {
+        self.message.compress().to_bytes()
+    }
+
+    /// Finish the exchange with the message of the other side,
+    /// getting the confirmations of both sides bound to their node IDs and to the object.
+    pub(crate) fn finish(
+        self,
+        other_message: &[u8; PAKE_MESSAGE_BYTES],
+        receiver_node_id: &NodeId,
+        sender_node_id: &NodeId,
+    ) -> Result<Confirmations> {
+        let other_point = CompressedRistretto(*other_message)
+            .decompress()
+            .ok_or_else(|| anyhow!("The message of the other peer is not a valid point"))?;
+
+        let shared_point =
+            self.scalar * (other_point - self.secret.password * get_side_point(self.side.other()));
+
+        let (receiver_message, sender_message) = match self.side {
+            Side::Sender => (other_point, self.message),
+            Side::Receiver => (self.message, other_point),
+        };
+
+        let key = Sha256::new()
+            .chain_update(PAKE_CONTEXT)
+            .chain_update(receiver_node_id.as_bytes())
+            .chain_update(sender_node_id.as_bytes())
+            .chain_update(receiver_message.compress().as_bytes())
+            .chain_update(sender_message.compress().as_bytes())
+            .chain_update(shared_point.compress().as_bytes())
+            .chain_update(self.secret.password.as_bytes())
+            .chain_update(self.secret.digest)
+            .finalize();
+
+        Ok(Confirmations {
+            side: self.side,
+            key: key.into(),
+        })
+    }
+}
+
+/// The confirmations of both sides of a finished exchange,
+/// that only match if both sides know the secret and agree on the node IDs and the object.
+pub(crate) struct Confirmations {
+    side: Side,
+    key: [u8; 32],
+}
+
+impl Confirmations {
+    /// The confirmation that must be sent to the other side.
+    pub(crate) fn own(&self) -> Result<[u8; PAKE_MESSAGE_BYTES]> {
+        Ok(self
+            .get_confirmation(self.side)?
+            .finalize()
+            .into_bytes()
+            .into())
+    }
+
+    /// Whether the confirmation received from the other side is right.
+    pub(crate) fn verify_other(&self, confirmation: &[u8; PAKE_MESSAGE_BYTES]) -> Result<bool> {
+        Ok(self
+            .get_confirmation(self.side.other())?
+            .verify_slice(confirmation)
+            .is_ok())
+    }
+
+    /// Get the confirmation of a side, so they can't be reflected to the side that sent them.
+    fn get_confirmation(&self, side: Side) -> Result<Hmac<Sha256>> {
+        let mut confirmation = Hmac::<Sha256>::new_from_slice(&self.key)
+            .map_err(|error| anyhow!("Unable to use the key of the exchange: {error}"))?;
+
+        confirmation.update(match side {
+            Side::Sender => b"sender",
+            Side::Receiver => b"receiver",
+        });
+
+        Ok(confirmation)
+    }
+}
+
+/// Get the point of SPAKE2 of a side, whose discrete logarithm is unknown.
+fn get_side_point(side: Side) -> RistrettoPoint {
+    let seed = match side {
+        Side::Sender => SENDER_POINT_SEED,
+        Side::Receiver => RECEIVER_POINT_SEED,
+    };
+
+    RistrettoPoint::from_uniform_bytes(&Sha512::digest(seed).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh::SecretKey;
+
+    use super::*;
+
+    /// Run a whole exchange, returning whether each side accepts the confirmation of the other one.
+    fn exchange(
+        receiver_secret: ObjectSecret,
+        sender_secret: ObjectSecret,
+        receiver_node_id: &NodeId,
+        sender_node_id: &NodeId,
+    ) -> Result<(bool, bool)> {
+        let receiver = Pake::start(Side::Receiver, receiver_secret);
+        let sender = Pake::start(Side::Sender, sender_secret);
+
+        let receiver_message = receiver.message();
+        let sender_message = sender.message();
+
+        let receiver = receiver.finish(&sender_message, receiver_node_id, sender_node_id)?;
+        let sender = sender.finish(&receiver_message, receiver_node_id, sender_node_id)?;
+
+        Ok((
+            receiver.verify_other(&sender.own()?)?,
+            sender.verify_other(&receiver.own()?)?,
+        ))
+    }
+
+    fn make_object_entry(file_hash: HashWrapper) -> ObjectEntry {
+        ObjectEntry {
+            node_address: crate::object::NodeAddrWrapper(
+                SecretKey::from_bytes(&rand::random()).public().into(),
+            ),
+            file_hash,
+            name: String::from("data.bin"),
+            kind: ObjectKind::File,
+            size: 3,
+            folder_format: None,
+            contents: None,
+            requires_password: false,
+        }
+    }
+
+    #[test]
+    fn test_pake_only_confirms_the_same_code_and_object() -> Result<()> {
+        let receiver_node_id = SecretKey::from_bytes(&rand::random()).public();
+        let sender_node_id = SecretKey::from_bytes(&rand::random()).public();
+
+        let code = ObjectCode::generate(String::from("the-brave-cat-jumps"));
+        let wrong_code = ObjectCode {
+            id: code.id.clone(),
+            secret: String::from("000000"),
+        };
+
+        let object_entry = make_object_entry(HashWrapper(iroh_blobs::Hash::new(b"abc")));
+        let swapped_object_entry = make_object_entry(HashWrapper(iroh_blobs::Hash::new(b"evil")));
+
+        let secret = ObjectSecret::new(&code, &object_entry)?;
+
+        assert_eq!(
+            exchange(
+                secret.clone(),
+                secret.clone(),
+                &receiver_node_id,
+                &sender_node_id
+            )?,
+            (true, true)
+        );
+
+        // The address of the sender isn't part of the description
+        assert_eq!(
+            exchange(
+                ObjectSecret::new(&code, &make_object_entry(object_entry.file_hash.clone()))?,
+                secret.clone(),
+                &receiver_node_id,
+                &sender_node_id
+            )?,
+            (true, true)
+        );
+
+        assert_eq!(
+            exchange(
+                ObjectSecret::new(&wrong_code, &object_entry)?,
+                secret.clone(),
+                &receiver_node_id,
+                &sender_node_id
+            )?,
+            (false, false)
+        );
+
+        assert_eq!(
+            exchange(
+                ObjectSecret::new(&code, &swapped_object_entry)?,
+                secret.clone(),
+                &receiver_node_id,
+                &sender_node_id
+            )?,
+            (false, false)
+        );
+
+        // The receiver has been connected to another node than the one that the sender sees
+        let other_node_id = SecretKey::from_bytes(&rand::random()).public();
+        let receiver = Pake::start(Side::Receiver, secret.clone());
+        let sender = Pake::start(Side::Sender, secret);
+        let receiver_message = receiver.message();
+
+        let receiver = receiver.finish(&sender.message(), &receiver_node_id, &other_node_id)?;
+        let sender = sender.finish(&receiver_message, &receiver_node_id, &sender_node_id)?;
+
+        assert!(!receiver.verify_other(&sender.own()?)?);
+        assert!(!sender.verify_other(&receiver.own()?)?);
+
+        Ok(())
+    }
+}
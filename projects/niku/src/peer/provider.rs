@@ -0,0 +1,501 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use futures_lite::future::Boxed;
+use futures_lite::{stream, Stream};
+use iroh::NodeId;
+use iroh_blobs::provider::{CustomEventSender, Event};
+use iroh_blobs::{BlobFormat, Hash};
+use log::debug;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+
+use super::approval::Gate;
+use super::{Peer, PeerError};
+use crate::object::{HashWrapper, ObjectEntry};
+use crate::progress::ProviderEvent;
+
+/// Number of provider events that a subscriber can fall behind before missing the oldest ones.
+const PROVIDER_EVENT_CAPACITY: usize = 1024;
+
+/// Message handled by [handle_provider_events], in the same order as they happen.
+#[derive(Debug)]
+pub(crate) enum ProviderMessage {
+    /// A receiver has connected.
+    Connected { connection_id: u64, node_id: NodeId },
+
+    /// The connection of a receiver has been closed.
+    Disconnected { connection_id: u64 },
+
+    /// A receiver has failed to authenticate.
+    AuthenticationFailed {
+        node_id: NodeId,
+        remaining_attempts: u32,
+        is_locked: bool,
+    },
+
+    /// An event of the [iroh_blobs] provider.
+    Blobs(Event),
+}
+
+/// Error code used to close the connections of the receivers that request the object once its limit of downloads is reached.
+const DOWNLOAD_LIMIT_ERROR_CODE: u32 = 2;
+
+/// Sender of the events of the [iroh_blobs] provider, that are handled by [handle_provider_events].
+///
+/// The provider waits for the events of each request before serving it,
+/// so the requests of the limited object are refused here once it has no downloads left.
+#[derive(Debug)]
+pub(crate) struct ProviderEventSender {
+    pub(crate) messages: mpsc::UnboundedSender<ProviderMessage>,
+    pub(crate) gate: Arc<Gate>,
+}
+
+impl CustomEventSender for ProviderEventSender {
+    fn send(&self, event: Event) -> Boxed<()> {
+        self.try_send(event.clone());
+
+        let gate = self.gate.clone();
+
+        Box::pin(async move {
+            if count_download(&gate, &event).await {
+                return;
+            }
+
+            if let Event::GetRequestReceived { connection_id, .. } = event {
+                debug!(
+                    "Refusing the request of the object, its limit of downloads has been reached"
+                );
+
+                if let Some(connection) = gate.connections.lock().await.get(&connection_id) {
+                    connection.close(DOWNLOAD_LIMIT_ERROR_CODE.into(), b"download limit reached");
+                }
+            }
+        })
+    }
+
+    fn try_send(&self, event: Event) {
+        // The messages are only dropped once the peer is shutting down
+        let _ = self.messages.send(ProviderMessage::Blobs(event));
+    }
+}
+
+/// Limit of the downloads of an object.
+#[derive(Debug)]
+pub(crate) struct DownloadLimit {
+    /// The hash of the object.
+    hash: Hash,
+
+    /// The format of the blob of the object.
+    format: BlobFormat,
+
+    /// The hashes of the children of the hash sequence of the object, empty for a single blob.
+    children: HashSet<Hash>,
+
+    /// The number of downloads that can still be counted.
+    remaining_downloads: u64,
+
+    /// The connections downloading the object, with whether their download has already been counted.
+    ///
+    /// The downloads that haven't been counted yet hold one of the remaining downloads until they disconnect.
+    downloads: HashMap<u64, bool>,
+
+    /// The requests of the object or of its children being transferred, indexed by their connection and request IDs,
+    /// with the requested hash and whether the transfer includes the children of a hash sequence.
+    requests: HashMap<(u64, u64), (Hash, bool)>,
+}
+
+impl DownloadLimit {
+    /// Whether a new connection can start downloading the object,
+    /// that is the remaining downloads aren't held by the downloads in progress.
+    fn can_start_download(&self) -> bool {
+        let downloads_in_progress = self
+            .downloads
+            .values()
+            .filter(|is_counted| !**is_counted)
+            .count() as u64;
+
+        downloads_in_progress < self.remaining_downloads
+    }
+}
+
+impl Peer {
+    /// Limit the number of times that an object entry can be downloaded,
+    /// once it's reached no new receiver is served.
+    ///
+    /// Each download holds one of the remaining downloads from its first request of the object until it disconnects,
+    /// so the requests of the receivers that connect meanwhile are refused if there are no more left.
+    /// The complete downloads are counted once they finish, and the downloads of a selection of the files
+    /// of a folder or bundle once the first file is sent, as they request each file individually.
+    /// The downloads that are interrupted before being counted don't count.
+    pub async fn limit_downloads(
+        &self,
+        object_entry: &ObjectEntry,
+        max_downloads: u64,
+    ) -> Result<(), PeerError> {
+        let format = object_entry.blob_format();
+
+        let children = if format == BlobFormat::HashSeq {
+            self.get_hash_seq_children(object_entry).await?.collect()
+        } else {
+            HashSet::new()
+        };
+
+        *self.gate.download_limit.lock().await = Some(DownloadLimit {
+            hash: object_entry.file_hash.0,
+            format,
+            children,
+            remaining_downloads: max_downloads,
+            downloads: HashMap::new(),
+            requests: HashMap::new(),
+        });
+
+        self.gate.is_exhausted.send_replace(max_downloads == 0);
+
+        Ok(())
+    }
+
+    /// Subscribe to the events of the transfers of the objects of the peer to its receivers.
+    ///
+    /// The oldest events are skipped if the subscriber falls too far behind.
+    pub fn subscribe_provider_events(&self) -> impl Stream<Item = ProviderEvent> + Send + 'static {
+        stream::unfold(
+            self.provider_events.subscribe(),
+            |mut provider_events| async move {
+                loop {
+                    match provider_events.recv().await {
+                        Ok(event) => return Some((event, provider_events)),
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("Skipped {skipped} provider events")
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            },
+        )
+    }
+
+    /// Make the channel where the provider events are published.
+    pub(super) fn make_provider_event_channel() -> broadcast::Sender<ProviderEvent> {
+        broadcast::Sender::new(PROVIDER_EVENT_CAPACITY)
+    }
+
+    /// Wait until the limit of downloads is reached and the last receiver has disconnected.
+    ///
+    /// Never returns if the downloads aren't limited.
+    pub async fn wait_for_download_limit(&self) {
+        let _ = self
+            .gate
+            .is_exhausted
+            .subscribe()
+            .wait_for(|is_exhausted| *is_exhausted)
+            .await;
+
+        let _ = self
+            .gate
+            .served_connections
+            .subscribe()
+            .wait_for(|served_connections| *served_connections == 0)
+            .await;
+    }
+}
+
+/// Handle the messages of the provider until it's shutdown,
+/// publishing them as [ProviderEvent]s.
+pub(crate) async fn handle_provider_events(
+    mut messages: mpsc::UnboundedReceiver<ProviderMessage>,
+    provider_events: broadcast::Sender<ProviderEvent>,
+) {
+    // The receivers indexed by the ID of their connection
+    let mut nodes: HashMap<u64, NodeId> = HashMap::new();
+
+    // The bytes of the blobs already sent by each request, indexed by their connection and request IDs
+    let mut sent_bytes: HashMap<(u64, u64), u64> = HashMap::new();
+
+    while let Some(message) = messages.recv().await {
+        let event = match message {
+            ProviderMessage::Connected {
+                connection_id,
+                node_id,
+            } => {
+                nodes.insert(connection_id, node_id);
+
+                Some(ProviderEvent::Connected { node_id })
+            }
+
+            ProviderMessage::Disconnected { connection_id } => nodes
+                .remove(&connection_id)
+                .map(|node_id| ProviderEvent::Disconnected { node_id }),
+
+            ProviderMessage::AuthenticationFailed {
+                node_id,
+                remaining_attempts,
+                is_locked,
+            } => Some(ProviderEvent::AuthenticationFailed {
+                node_id,
+                remaining_attempts,
+                is_locked,
+            }),
+
+            ProviderMessage::Blobs(event) => get_provider_event(&event, &nodes, &mut sent_bytes),
+        };
+
+        if let Some(event) = event {
+            // There may be no subscriber
+            let _ = provider_events.send(event);
+        }
+    }
+}
+
+/// Get the public event of an event of the [iroh_blobs] provider, if it's relevant.
+fn get_provider_event(
+    event: &Event,
+    nodes: &HashMap<u64, NodeId>,
+    sent_bytes: &mut HashMap<(u64, u64), u64>,
+) -> Option<ProviderEvent> {
+    match *event {
+        Event::GetRequestReceived {
+            connection_id,
+            request_id,
+            hash,
+        } => {
+            sent_bytes.insert((connection_id, request_id), 0);
+
+            Some(ProviderEvent::TransferStarted {
+                node_id: *nodes.get(&connection_id)?,
+                request_id,
+                hash: HashWrapper(hash),
+            })
+        }
+
+        Event::TransferProgress {
+            connection_id,
+            request_id,
+            end_offset,
+            ..
+        } => Some(ProviderEvent::TransferProgress {
+            node_id: *nodes.get(&connection_id)?,
+            request_id,
+            bytes: sent_bytes.get(&(connection_id, request_id))? + end_offset,
+        }),
+
+        Event::TransferBlobCompleted {
+            connection_id,
+            request_id,
+            size,
+            ..
+        } => {
+            *sent_bytes.get_mut(&(connection_id, request_id))? += size;
+
+            None
+        }
+
+        Event::TransferCompleted {
+            connection_id,
+            request_id,
+            ref stats,
+        } => {
+            sent_bytes.remove(&(connection_id, request_id));
+
+            Some(ProviderEvent::TransferCompleted {
+                node_id: *nodes.get(&connection_id)?,
+                request_id,
+                bytes: stats.send.total().size,
+            })
+        }
+
+        Event::TransferAborted {
+            connection_id,
+            request_id,
+            ..
+        } => {
+            sent_bytes.remove(&(connection_id, request_id));
+
+            Some(ProviderEvent::TransferAborted {
+                node_id: *nodes.get(&connection_id)?,
+                request_id,
+            })
+        }
+
+        _ => None,
+    }
+}
+
+/// Count the downloads of the limited object, if there is any,
+/// returning whether the request of the event can be served.
+async fn count_download(gate: &Gate, event: &Event) -> bool {
+    let mut download_limit = gate.download_limit.lock().await;
+
+    let Some(limit) = download_limit.as_mut() else {
+        return true;
+    };
+
+    match *event {
+        Event::GetRequestReceived {
+            connection_id,
+            request_id,
+            hash,
+        } if hash == limit.hash || limit.children.contains(&hash) => {
+            if !limit.downloads.contains_key(&connection_id) {
+                if !limit.can_start_download() {
+                    return false;
+                }
+
+                limit.downloads.insert(connection_id, false);
+            }
+
+            limit
+                .requests
+                .insert((connection_id, request_id), (hash, false));
+        }
+
+        Event::TransferHashSeqStarted {
+            connection_id,
+            request_id,
+            ..
+        } => {
+            if let Some((_, has_children)) = limit.requests.get_mut(&(connection_id, request_id)) {
+                *has_children = true;
+            }
+        }
+
+        Event::TransferCompleted {
+            connection_id,
+            request_id,
+            ..
+        } => {
+            let is_counted = match limit.requests.remove(&(connection_id, request_id)) {
+                // The hash sequence of a folder or bundle is also requested alone to select its files
+                Some((hash, has_children)) if hash == limit.hash => {
+                    has_children || limit.format == BlobFormat::Raw
+                }
+
+                // The first file of a selection
+                Some(_) => true,
+
+                None => false,
+            };
+
+            if is_counted && limit.downloads.get(&connection_id) == Some(&false) {
+                limit.downloads.insert(connection_id, true);
+                limit.remaining_downloads = limit.remaining_downloads.saturating_sub(1);
+                debug!(
+                    "The object has been downloaded, {} downloads remaining",
+                    limit.remaining_downloads
+                );
+
+                if limit.remaining_downloads == 0 {
+                    gate.is_exhausted.send_replace(true);
+                }
+            }
+        }
+
+        Event::TransferAborted {
+            connection_id,
+            request_id,
+            ..
+        } => {
+            limit.requests.remove(&(connection_id, request_id));
+        }
+
+        _ => (),
+    }
+
+    true
+}
+
+/// Forget the download of a connection that has been closed,
+/// so the remaining download that it was holding is released if it wasn't counted.
+pub(crate) async fn finish_download(gate: &Gate, connection_id: u64) {
+    if let Some(limit) = gate.download_limit.lock().await.as_mut() {
+        limit.downloads.remove(&connection_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_blobs::provider::TransferStats;
+
+    use super::*;
+
+    /// Drive the start of a request of a hash by a connection, returning whether it's served.
+    async fn start_transfer(gate: &Gate, connection_id: u64, request_id: u64, hash: Hash) -> bool {
+        let event = Event::GetRequestReceived {
+            connection_id,
+            request_id,
+            hash,
+        };
+
+        count_download(gate, &event).await
+    }
+
+    /// Drive the completion of a request.
+    async fn complete_transfer(gate: &Gate, connection_id: u64, request_id: u64) {
+        let event = Event::TransferCompleted {
+            connection_id,
+            request_id,
+            stats: Box::new(TransferStats::default()),
+        };
+
+        count_download(gate, &event).await;
+    }
+
+    async fn remaining_downloads(gate: &Gate) -> Option<u64> {
+        gate.download_limit
+            .lock()
+            .await
+            .as_ref()
+            .map(|limit| limit.remaining_downloads)
+    }
+
+    #[tokio::test]
+    async fn test_downloads_of_each_child_are_counted() {
+        let hash = Hash::new(b"hash sequence");
+        let children: Vec<Hash> = (0..3u8).map(|index| Hash::new([index])).collect();
+
+        let gate = Gate::default();
+        *gate.download_limit.lock().await = Some(DownloadLimit {
+            hash,
+            format: BlobFormat::HashSeq,
+            children: children.iter().copied().collect(),
+            remaining_downloads: 2,
+            downloads: HashMap::new(),
+            requests: HashMap::new(),
+        });
+
+        // The hash sequence alone isn't a download, but the first child is
+        assert!(start_transfer(&gate, 1, 0, hash).await);
+        complete_transfer(&gate, 1, 0).await;
+        assert_eq!(remaining_downloads(&gate).await, Some(2));
+
+        assert!(start_transfer(&gate, 1, 1, children[0]).await);
+        complete_transfer(&gate, 1, 1).await;
+        assert_eq!(remaining_downloads(&gate).await, Some(1));
+
+        // The rest of the children of the same download aren't counted again
+        assert!(start_transfer(&gate, 1, 2, children[1]).await);
+        complete_transfer(&gate, 1, 2).await;
+        assert_eq!(remaining_downloads(&gate).await, Some(1));
+
+        // A download in progress holds the last remaining download, so the concurrent ones are refused
+        assert!(start_transfer(&gate, 2, 0, children[2]).await);
+        assert!(!start_transfer(&gate, 3, 0, hash).await);
+
+        // Until it's interrupted without being counted
+        finish_download(&gate, 2).await;
+        assert!(start_transfer(&gate, 3, 0, children[2]).await);
+        complete_transfer(&gate, 3, 0).await;
+        assert_eq!(remaining_downloads(&gate).await, Some(0));
+        assert!(*gate.is_exhausted.borrow());
+
+        // The downloads that have been counted can go on, but no new one can start
+        assert!(start_transfer(&gate, 1, 3, children[2]).await);
+        assert!(!start_transfer(&gate, 4, 0, hash).await);
+    }
+}
@@ -0,0 +1,57 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use anyhow::Result;
+
+use super::{Peer, PeerError, MAX_TEXT_BYTES};
+use crate::object::{HashWrapper, NodeAddrWrapper, ObjectEntry, ObjectKind};
+
+impl Peer {
+    /// Creates a new object entry for a short text with the given name.
+    pub async fn create_text_object_entry(
+        &mut self,
+        name: String,
+        text: String,
+    ) -> Result<ObjectEntry, PeerError> {
+        if text.len() as u64 > MAX_TEXT_BYTES {
+            return Err(PeerError::TextTooLong);
+        }
+
+        let blob = self.blobs.client().add_bytes(text).await?;
+
+        Ok(ObjectEntry {
+            node_address: NodeAddrWrapper(self.router.endpoint().node_addr().await?),
+            file_hash: HashWrapper(blob.hash),
+            kind: ObjectKind::Text,
+            name,
+            size: blob.size,
+            folder_format: None,
+            contents: None,
+            requires_password: false,
+        })
+    }
+
+    /// Read the text of a previously downloaded text object entry.
+    ///
+    /// # Safety
+    /// Doesn't check neither if the given object is for a text
+    /// or if the object has been downloaded beforehand into the Iroh store.
+    pub async unsafe fn read_text_object_entry(
+        &self,
+        object_entry: &ObjectEntry,
+    ) -> Result<String, PeerError> {
+        let mut reader = self.blobs.client().read(object_entry.file_hash.0).await?;
+
+        // The size announced by the sender may be different from the real one
+        if reader.size() > MAX_TEXT_BYTES {
+            return Err(PeerError::TextTooLong);
+        }
+
+        let text = reader.read_to_bytes().await?;
+
+        String::from_utf8(text.to_vec()).map_err(|_| PeerError::NotUnicodeText)
+    }
+}
@@ -0,0 +1,197 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::HashSet;
+use std::fs::{FileType, Metadata};
+use std::path::{Path, PathBuf};
+
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+
+use super::{PackOptions, PeerError};
+
+/// Name of the files with ignore rules specific to NIKU, with the same syntax as `.gitignore` files.
+const NIKU_IGNORE_FILE_NAME: &str = ".nikuignore";
+
+/// File, folder or symbolic link to be packed.
+pub(crate) struct WalkEntry {
+    pub(crate) path: PathBuf,
+
+    /// The path relative to the walked path, empty for the walked path itself.
+    pub(crate) relative_path: PathBuf,
+
+    pub(crate) file_type: FileType,
+
+    pub(crate) metadata: Metadata,
+}
+
+/// The entries to be packed from a path, sorted by their path,
+/// with the number of files and folders that were skipped by the pack options, and the bytes of the skipped files.
+pub(crate) struct Walk {
+    pub(crate) entries: Vec<WalkEntry>,
+    pub(crate) skipped_entries: u64,
+    pub(crate) skipped_bytes: u64,
+}
+
+impl Walk {
+    /// Walk a file or a folder, skipping the files ignored by the given options.
+    pub(crate) fn new(path: &Path, options: &PackOptions) -> Result<Walk, PeerError> {
+        let mut overrides = OverrideBuilder::new(path);
+
+        for glob in &options.include {
+            overrides.add(glob)?;
+        }
+
+        for glob in &options.exclude {
+            overrides.add(&format!("!{glob}"))?;
+        }
+
+        let mut walk_builder = WalkBuilder::new(path);
+        walk_builder
+            .standard_filters(false)
+            .git_ignore(options.use_ignore_files)
+            .require_git(false)
+            .parents(options.use_ignore_files)
+            .follow_links(options.follow_links)
+            .overrides(overrides.build()?)
+            .sort_by_file_name(|a, b| a.cmp(b));
+
+        if options.use_ignore_files {
+            walk_builder
+                .add_custom_ignore_filename(NIKU_IGNORE_FILE_NAME)
+                // The Git repository itself is never part of the files tracked by Git
+                .filter_entry(|entry| entry.file_name() != ".git");
+        }
+
+        let mut entries = Vec::new();
+
+        for entry in walk_builder.build() {
+            let entry = entry?;
+
+            let Some(file_type) = entry.file_type() else {
+                continue;
+            };
+
+            entries.push(WalkEntry {
+                relative_path: entry.path().strip_prefix(path)?.to_owned(),
+                metadata: entry.metadata()?,
+                path: entry.into_path(),
+                file_type,
+            });
+        }
+
+        // Only keep the folders with files to be packed inside when only some files are included
+        if !options.include.is_empty() {
+            let used_folders: HashSet<PathBuf> = entries
+                .iter()
+                .filter(|entry| !entry.file_type.is_dir())
+                .flat_map(|entry| entry.relative_path.ancestors().skip(1))
+                .map(Path::to_owned)
+                .collect();
+
+            entries.retain(|entry| {
+                !entry.file_type.is_dir() || used_folders.contains(&entry.relative_path)
+            });
+        }
+
+        // The skipped entries are the children of the walked folders that weren't walked,
+        // without walking the skipped folders, as they may be huge like `target` or `node_modules`
+        let walked_paths: HashSet<&Path> =
+            entries.iter().map(|entry| entry.path.as_path()).collect();
+        let mut skipped_entries = 0;
+        let mut skipped_bytes = 0;
+
+        for entry in entries.iter().filter(|entry| entry.file_type.is_dir()) {
+            for child in std::fs::read_dir(&entry.path)? {
+                let child = child?;
+
+                if !walked_paths.contains(child.path().as_path()) {
+                    skipped_entries += 1;
+
+                    if child.file_type()?.is_file() {
+                        skipped_bytes += child.metadata()?.len();
+                    }
+                }
+            }
+        }
+
+        Ok(Walk {
+            entries,
+            skipped_entries,
+            skipped_bytes,
+        })
+    }
+
+    /// The total number of bytes of the files to be packed.
+    pub(crate) fn file_bytes(&self) -> u64 {
+        self.entries
+            .iter()
+            .filter(|entry| entry.file_type.is_file())
+            .map(|entry| entry.metadata.len())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn get_walked_paths(walk: &Walk) -> Vec<&str> {
+        walk.entries
+            .iter()
+            .filter_map(|entry| entry.relative_path.to_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_walk_skips_ignored_and_excluded_files() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+
+        fs::create_dir_all(root.join(".git"))?;
+        fs::create_dir_all(root.join("src"))?;
+        fs::create_dir_all(root.join("target"))?;
+        fs::write(root.join(".git/config"), "1")?;
+        fs::write(root.join(".gitignore"), "target/\n")?;
+        fs::write(root.join(".nikuignore"), "*.log\n")?;
+        fs::write(root.join("debug.log"), "22")?;
+        fs::write(root.join("notes.txt"), "333")?;
+        fs::write(root.join("src/main.rs"), "4444")?;
+        fs::write(root.join("target/main"), "55555")?;
+
+        let walk = Walk::new(
+            root,
+            &PackOptions {
+                exclude: vec!["*.txt".to_owned()],
+                ..PackOptions::default()
+            },
+        )?;
+
+        assert_eq!(
+            get_walked_paths(&walk),
+            ["", ".gitignore", ".nikuignore", "src", "src/main.rs"]
+        );
+        // The ignored folders are skipped as a whole
+        assert_eq!(walk.skipped_entries, 4);
+        assert_eq!(walk.skipped_bytes, 5);
+
+        let walk = Walk::new(
+            root,
+            &PackOptions {
+                use_ignore_files: false,
+                include: vec!["*.rs".to_owned()],
+                ..PackOptions::default()
+            },
+        )?;
+
+        assert_eq!(get_walked_paths(&walk), ["", "src", "src/main.rs"]);
+        assert_eq!(walk.skipped_entries, 6);
+
+        Ok(())
+    }
+}
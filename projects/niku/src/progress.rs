@@ -0,0 +1,146 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Events used to report the progress of the long running operations of a peer.
+
+use iroh::NodeId;
+
+use crate::object::HashWrapper;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Progress of the download of an object entry.
+pub enum DownloadProgress {
+    /// Connected to the peer that is hosting the object.
+    Connected,
+
+    /// The total number of bytes that the object has.
+    Total(u64),
+
+    /// The number of bytes of the object available so far,
+    /// including the ones that were already downloaded beforehand.
+    Received(u64),
+
+    /// The download has finished.
+    Done,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Progress of the export of a downloaded object entry.
+pub enum ExportProgress {
+    /// The total number of bytes that are going to be exported.
+    Total(u64),
+
+    /// The exported data must be extracted, the value is the total number of bytes to be extracted.
+    ///
+    /// The following [ExportProgress::Exported] events refer to the extracted bytes.
+    Extracting(u64),
+
+    /// The number of bytes exported so far.
+    Exported(u64),
+
+    /// The export has finished.
+    Done,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Progress of the packing of a folder, or of a stream, into an object entry.
+pub enum PackProgress {
+    /// Number of files and folders that are skipped by the pack options, and the bytes of the skipped files.
+    ///
+    /// The content of the skipped folders isn't walked, so it's not counted.
+    Skipped {
+        /// The number of skipped files and folders.
+        entries: u64,
+
+        /// The number of bytes of the skipped files.
+        bytes: u64,
+    },
+
+    /// The total number of bytes that are going to be packed.
+    Total(u64),
+
+    /// Started packing the file with the given path inside the object.
+    File(String),
+
+    /// The number of bytes packed so far, updated while each file is packed.
+    Packed(u64),
+
+    /// The packing has finished.
+    Done,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Event of the transfer of the objects of a peer to its receivers.
+pub enum ProviderEvent {
+    /// A receiver has connected to download the objects.
+    Connected {
+        /// The [iroh] node ID of the receiver.
+        node_id: NodeId,
+    },
+
+    /// A receiver has requested a blob, the object itself or another one like its listing.
+    TransferStarted {
+        /// The [iroh] node ID of the receiver.
+        node_id: NodeId,
+
+        /// The ID of the request, unique within the connection of the receiver.
+        request_id: u64,
+
+        /// The hash of the requested blob.
+        hash: HashWrapper,
+    },
+
+    /// The number of bytes of the requested blob, and of its children, sent so far.
+    TransferProgress {
+        /// The [iroh] node ID of the receiver.
+        node_id: NodeId,
+
+        /// The ID of the request, unique within the connection of the receiver.
+        request_id: u64,
+
+        /// The number of bytes sent so far.
+        bytes: u64,
+    },
+
+    /// The transfer of a request has finished.
+    TransferCompleted {
+        /// The [iroh] node ID of the receiver.
+        node_id: NodeId,
+
+        /// The ID of the request, unique within the connection of the receiver.
+        request_id: u64,
+
+        /// The number of bytes sent, including the data used to verify them.
+        bytes: u64,
+    },
+
+    /// The transfer of a request has been aborted, usually because the receiver has disconnected.
+    TransferAborted {
+        /// The [iroh] node ID of the receiver.
+        node_id: NodeId,
+
+        /// The ID of the request, unique within the connection of the receiver.
+        request_id: u64,
+    },
+
+    /// A receiver has disconnected.
+    Disconnected {
+        /// The [iroh] node ID of the receiver.
+        node_id: NodeId,
+    },
+
+    /// A receiver has failed to authenticate, it doesn't know the code or the password of the object.
+    AuthenticationFailed {
+        /// The [iroh] node ID of the receiver.
+        node_id: NodeId,
+
+        /// The number of times that the receiver can still try to authenticate.
+        remaining_attempts: u32,
+
+        /// Whether too many receivers have failed to authenticate, so no new receiver is authenticated.
+        is_locked: bool,
+    },
+}
@@ -20,7 +20,7 @@ use niku::object::ObjectEntry;
 use thiserror::Error;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
-use tracing::{error, info};
+use tracing::info;
 
 const ENV_VARS_PREFIX: &str = "APP_NIKU_BACKEND_";
 
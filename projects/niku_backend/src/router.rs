@@ -4,6 +4,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+mod delete_objects_id;
 mod get_objects_id;
 mod post_objects_id_keep_alive;
 mod put_objects;
@@ -23,6 +24,7 @@ use utoipa_axum::router::OpenApiRouter;
 use utoipa_axum::routes;
 use utoipa_swagger_ui::SwaggerUi;
 
+use crate::router::delete_objects_id::*;
 use crate::router::get_objects_id::*;
 use crate::router::post_objects_id_keep_alive::*;
 use crate::router::put_objects::*;
@@ -33,6 +35,7 @@ pub(crate) fn create_router(state: Arc<Mutex<SharedData>>) -> Router {
         .routes(routes!(
             put_objects,
             get_objects_id,
+            delete_objects_id,
             post_objects_id_keep_alive
         ))
         .with_state(state)
@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::sync::Arc;
+
+use axum::extract::{Json, Path, State};
+use niku::backend::{ErrorResponse, ObjectKeepAliveRequest};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::errors::ServerError;
+use crate::SharedData;
+
+#[utoipa::path(
+    delete,
+    path = "/objects/{id}",
+    params(("id" = String, Path, description = "The ID of the object.")),
+    request_body = ObjectKeepAliveRequest,
+    responses((status = OK), (status = NOT_FOUND, body = ErrorResponse))
+)]
+/// Unregister an object before the end of its lifetime.
+///
+/// The keep alive key of the object is required, so only its sender can unregister it.
+pub(super) async fn delete_objects_id(
+    State(locked_state): State<Arc<Mutex<SharedData>>>,
+    Path(id): Path<String>,
+    Json(keep_alive_request): Json<ObjectKeepAliveRequest>,
+) -> Result<(), ServerError> {
+    let mut state = locked_state.lock().await;
+
+    let keep_alive_entry = state
+        .keep_alive_entries
+        .get(&keep_alive_request.keep_alive_key)
+        .filter(|keep_alive_entry| keep_alive_entry.object_id == id)
+        .ok_or(ServerError::UnknownKeepAliveKey)?;
+
+    keep_alive_entry.delete_task.abort();
+
+    state
+        .keep_alive_entries
+        .remove(&keep_alive_request.keep_alive_key);
+    state.objects.remove(&id);
+
+    if cfg!(debug_assertions) {
+        info!(%id, "Unregistered object");
+    }
+
+    Ok(())
+}
@@ -5,19 +5,24 @@
 
 use std::io;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::str::FromStr;
 
 use clap::{Parser, Subcommand};
-use log::error;
-use niku::peer::PeerError;
+use console::Term;
+use niku::object::{FolderFormat, ObjectKind};
+use niku::peer::{
+    Compression, ConflictPolicy, EntrySelection, ExtractLimits, PackOptions, PeerError,
+};
 use thiserror::Error;
-use tokio::task::{JoinError, JoinHandle};
-use tokio_util::sync::CancellationToken;
 
+mod progress;
 mod prune;
 mod receive;
 mod send;
 
+use receive::ReceiveOptions;
+use send::ServeOptions;
+
 #[derive(Parser)]
 #[command(name = "NIKU")]
 #[command(about, long_about = None)]
@@ -33,23 +38,149 @@ enum Commands {
     Prune,
 
     /// Send an object
-    Send { path: PathBuf },
+    Send {
+        #[arg(required_unless_present = "text")]
+        /// The paths of the files or folders to send, several paths are sent together as a bundle.
+        /// Use '-' to send the standard input as a file
+        paths: Vec<PathBuf>,
+
+        #[arg(short, long, conflicts_with = "paths")]
+        /// A short text to send instead of files, that is shown by the receiver.
+        /// Use '-' to read it from the standard input
+        text: Option<String>,
+
+        #[arg(short, long)]
+        /// The name of the file sent from the standard input, or of the text
+        name: Option<String>,
+
+        #[arg(short, long, default_value_t = FolderFormat::Collection)]
+        /// The format used to send a folder: 'collection' sends each file individually,
+        /// 'zip', 'tar' or 'tar.zst' send a single archive
+        format: FolderFormat,
+
+        #[arg(short, long)]
+        /// The compression of the archive of a folder: 'store', 'deflate' or 'zstd', optionally with a level like 'zstd:19'.
+        /// Defaults to 'deflate' for 'zip' and to 'zstd' for 'tar.zst'
+        compression: Option<Compression>,
+
+        #[arg(long)]
+        /// Don't preserve the permissions and modification times of the files
+        no_metadata: bool,
+
+        #[arg(long)]
+        /// Follow the symbolic links, sending their targets instead of the links themselves
+        follow_links: bool,
+
+        #[arg(long)]
+        /// Don't skip the files ignored by '.gitignore' and '.nikuignore' files, nor the '.git' folders
+        no_ignore: bool,
+
+        #[arg(long)]
+        /// Only send the files of the folders that match the glob, relative to each folder
+        include: Vec<String>,
+
+        #[arg(long)]
+        /// Skip the files of the folders that match the glob, relative to each folder
+        exclude: Vec<String>,
+
+        #[arg(short, long)]
+        /// Ask for the approval of each receiver before it can download the object
+        ask: bool,
+
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        /// Stop sending the object once it has been downloaded the given number of times,
+        /// refusing the receivers that would exceed it meanwhile.
+        /// The downloads of only some files of a folder or bundle count as soon as a file has been sent
+        max_downloads: Option<u64>,
+
+        #[arg(long, conflicts_with = "max_downloads")]
+        /// Stop sending the object once it has been downloaded, like '--max-downloads 1'
+        once: bool,
+
+        #[arg(short, long)]
+        /// Protect the object with a password that the receivers must know, it's asked for
+        password: bool,
+
+        #[arg(long, value_name = "PASSWORD", conflicts_with = "password")]
+        /// Protect the object with the given password that the receivers must know
+        password_value: Option<String>,
+    },
 
     /// Receive an object
     Receive {
-        /// The ID of the object to download
+        #[arg(value_name = "CODE")]
+        /// The code of the object to download as shown by the sender, its ID followed by its secret
         id: String,
 
         #[arg(short, long)]
         /// A custom output path where the object should be downloaded
         output: Option<PathBuf>,
 
+        #[arg(long, conflicts_with = "output")]
+        /// Write the received file into the standard output, only files and texts can be written
+        stdout: bool,
+
+        #[arg(short, long)]
+        /// Show the files inside of the object without downloading it
+        list: bool,
+
+        #[arg(long)]
+        /// Only download the files and folders of a folder or bundle that match the glob, relative to the object.
+        /// The folders sent as an archive are downloaded completely, and only the matching files are extracted
+        only: Vec<String>,
+
+        #[arg(short, long)]
+        /// The password of a protected object, it's asked for if not given
+        password: Option<String>,
+
         #[arg(short, long)]
         /// Download the object without asking the user
         yes: bool,
+
+        #[arg(long)]
+        /// The maximum number of bytes that can be extracted from the archive of a folder
+        max_size: Option<u64>,
+
+        #[arg(long, default_value_t = ExtractLimits::default().max_ratio)]
+        /// The maximum ratio between the bytes extracted from the archive of a folder and its size
+        max_ratio: u64,
+
+        #[arg(long, default_value_t = ExtractLimits::default().max_entries)]
+        /// The maximum number of files, folders and links that can be extracted from the archive of a folder
+        max_entries: u64,
+
+        #[arg(long, default_value = "ask")]
+        /// What to do when the object already exists: 'ask', 'overwrite', 'rename', 'skip' or 'fail'.
+        /// With '--yes', 'ask' renames the object
+        on_conflict: OnConflict,
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What to do when a received object already exists.
+enum OnConflict {
+    /// Ask the user for the policy once there is a conflict.
+    Ask,
+
+    /// Use the given policy.
+    Policy(ConflictPolicy),
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "ask" => Ok(OnConflict::Ask),
+            _ => text.parse().map(OnConflict::Policy).map_err(|_| {
+                format!(
+                    "Unknown conflict policy '{text}', expected 'ask', 'overwrite', 'rename', 'skip' or 'fail'"
+                )
+            }),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 /// Errors that may happen when running the app.
 pub enum CliError {
@@ -69,11 +200,38 @@ pub enum CliError {
     #[error("The given path is not for a file or for a folder")]
     ThePathIsNotAFileOrAFolder,
 
-    #[error("Unable to join a task: {0}")]
-    JoinTaskFailed(#[from] JoinError),
-
     #[error("The path where the file was downloaded is not UTF-8 (Unicode) encoded")]
     IntoStringError,
+
+    /// The text read from the standard input is not valid UTF-8.
+    #[error("The text of the standard input is not UTF-8 (Unicode) encoded")]
+    TextNotUtf8,
+
+    /// The standard input was given together with other paths.
+    #[error("The standard input ('-') can't be sent together with other paths")]
+    StdinWithOtherPaths,
+
+    /// The standard input was given without a name.
+    #[error("A name must be given with '--name' to send the standard input")]
+    MissingStdinName,
+
+    /// The received object can't be written into the standard output.
+    #[error(
+        "Only files and texts can be written into the standard output, but the object is a {0}"
+    )]
+    NotAFileForStdout(ObjectKind),
+
+    /// An output path was given for a received text, which is only shown.
+    #[error("Texts are shown instead of being written into a file, '--output' can't be used")]
+    OutputForText,
+
+    /// The approval of the receivers was asked without a terminal to answer it.
+    #[error("The approval of the receivers can only be asked ('--ask') from a terminal")]
+    AskWithoutTerminal,
+
+    /// The given password is empty.
+    #[error("The password can't be empty")]
+    EmptyPassword,
 }
 
 impl Cli {
@@ -81,36 +239,106 @@ impl Cli {
     pub async fn run(&self) -> Result<(), CliError> {
         match &self.command {
             Commands::Prune => Cli::prune().await?,
-            Commands::Send { path } => Cli::send(path).await?,
-            Commands::Receive { id, output, yes } => Cli::receive(id, output, !yes).await?,
+            Commands::Send {
+                paths,
+                text,
+                name,
+                format,
+                compression,
+                no_metadata,
+                follow_links,
+                no_ignore,
+                include,
+                exclude,
+                ask,
+                max_downloads,
+                once,
+                password,
+                password_value,
+            } => {
+                let pack_options = PackOptions {
+                    preserve_metadata: !no_metadata,
+                    follow_links: *follow_links,
+                    use_ignore_files: !no_ignore,
+                    include: include.clone(),
+                    exclude: exclude.clone(),
+                    compression: *compression,
+                };
+
+                let serve_options = ServeOptions {
+                    ask: *ask,
+                    max_downloads: if *once { Some(1) } else { *max_downloads },
+                    password: match password_value {
+                        Some(password) => Some(Cli::check_password(password.clone())?),
+                        None if *password => Some(Cli::ask_password("Password of the object: ")?),
+                        None => None,
+                    },
+                };
+
+                Cli::send(paths, text, name, *format, &pack_options, &serve_options).await?
+            }
+            Commands::Receive {
+                id,
+                list: true,
+                password,
+                ..
+            } => {
+                let password = password.clone().map(Cli::check_password).transpose()?;
+
+                Cli::list(id, &password).await?
+            }
+            Commands::Receive {
+                id,
+                output,
+                stdout,
+                list: false,
+                only,
+                password,
+                yes,
+                max_size,
+                max_ratio,
+                max_entries,
+                on_conflict,
+            } => {
+                let receive_options = ReceiveOptions {
+                    output: output.clone(),
+                    to_stdout: *stdout,
+                    should_ask: !yes,
+                    on_conflict: *on_conflict,
+                    extract_limits: ExtractLimits {
+                        max_bytes: *max_size,
+                        max_ratio: *max_ratio,
+                        max_entries: *max_entries,
+                    },
+                    selection: if only.is_empty() {
+                        None
+                    } else {
+                        Some(EntrySelection::new(only)?)
+                    },
+                };
+
+                let password = password.clone().map(Cli::check_password).transpose()?;
+
+                Cli::receive(id, &password, &receive_options).await?
+            }
         }
 
         Ok(())
     }
-}
-
-async fn generic_wait(message: &str) -> (JoinHandle<()>, CancellationToken) {
-    eprint!("{message}: .");
-
-    let token = CancellationToken::new();
-    let cloned_token = token.clone();
 
-    let task = tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_millis(20));
+    /// Ask the user for a password, without showing it.
+    fn ask_password(question: &str) -> Result<String, CliError> {
+        eprint!("{question}");
 
-        loop {
-            tokio::select! {
-                _ = interval.tick() => {
-                    eprint!(".");
-                }
+        Cli::check_password(Term::stderr().read_secure_line()?)
+    }
 
-                _ = cloned_token.cancelled() => {
-                    eprintln!();
-                    break;
-                }
-            }
+    /// Check that a password isn't empty, whether it's asked for or given.
+    fn check_password(password: String) -> Result<String, CliError> {
+        if password.is_empty() {
+            return Err(CliError::EmptyPassword);
         }
-    });
 
-    (task, token)
+        Ok(password)
+    }
 }
@@ -0,0 +1,122 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use console::Term;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Time between each line of progress when stderr is not a terminal.
+const LINE_OUTPUT_INTERVAL: Duration = Duration::from_secs(5);
+
+const PROGRESS_BAR_TEMPLATE: &str = "{msg}: [{bar:30}] {binary_bytes}/{binary_total_bytes} ({percent}%) {binary_bytes_per_sec}, ETA {eta}";
+
+const PROGRESS_WITHOUT_TOTAL_TEMPLATE: &str = "{msg}: {binary_bytes} {binary_bytes_per_sec}";
+
+/// Progress indicator of a long running operation printed on stderr.
+///
+/// Shows a progress bar when stderr is a terminal,
+/// otherwise it prints a line with the progress periodically.
+pub(super) struct ProgressReporter {
+    bar: ProgressBar,
+    is_term: bool,
+    last_line_output: Cell<Instant>,
+}
+
+impl ProgressReporter {
+    pub(super) fn new(message: &'static str) -> ProgressReporter {
+        ProgressReporter::with_template(message, Some(0), PROGRESS_BAR_TEMPLATE)
+    }
+
+    /// Progress of an operation whose total number of bytes is unknown, like reading a stream.
+    pub(super) fn without_total(message: &'static str) -> ProgressReporter {
+        ProgressReporter::with_template(message, None, PROGRESS_WITHOUT_TOTAL_TEMPLATE)
+    }
+
+    fn with_template(
+        message: &'static str,
+        total: Option<u64>,
+        template: &str,
+    ) -> ProgressReporter {
+        let is_term = Term::stderr().is_term();
+
+        let bar = ProgressBar::with_draw_target(total, ProgressDrawTarget::stderr())
+            .with_message(message);
+
+        #[allow(clippy::expect_used)]
+        bar.set_style(
+            ProgressStyle::with_template(template)
+                .expect("The progress bar templates are always valid")
+                .progress_chars("=> "),
+        );
+
+        ProgressReporter {
+            bar,
+            is_term,
+            last_line_output: Cell::new(Instant::now()),
+        }
+    }
+
+    /// Restart the progress with a new message and total number of bytes.
+    pub(super) fn restart(&self, message: &'static str, total: u64) {
+        self.bar.set_message(message);
+        self.bar.set_length(total);
+        self.bar.reset();
+    }
+
+    pub(super) fn set_total(&self, total: u64) {
+        self.bar.set_length(total);
+    }
+
+    pub(super) fn set_position(&self, position: u64) {
+        self.bar.set_position(position);
+
+        if !self.is_term && self.last_line_output.get().elapsed() >= LINE_OUTPUT_INTERVAL {
+            self.last_line_output.set(Instant::now());
+            self.print_line();
+        }
+    }
+
+    pub(super) fn finish(&self) {
+        if self.is_term {
+            self.bar.finish();
+            // Leave the cursor on the next line after the progress bar
+            eprintln!();
+        } else {
+            self.print_line();
+        }
+    }
+
+    fn print_line(&self) {
+        let position = self.bar.position();
+
+        let Some(total) = self.bar.length() else {
+            eprintln!(
+                "{}: {} {}/s",
+                self.bar.message(),
+                niku::format_bytes_with_unit(position),
+                niku::format_bytes_with_unit(self.bar.per_sec() as u64)
+            );
+
+            return;
+        };
+
+        let percent = position
+            .saturating_mul(100)
+            .checked_div(total)
+            .unwrap_or(100);
+
+        eprintln!(
+            "{}: {}/{} ({percent}%) {}/s, ETA {}s",
+            self.bar.message(),
+            niku::format_bytes_with_unit(position),
+            niku::format_bytes_with_unit(total),
+            niku::format_bytes_with_unit(self.bar.per_sec() as u64),
+            self.bar.eta().as_secs()
+        );
+    }
+}
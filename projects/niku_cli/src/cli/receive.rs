@@ -4,23 +4,61 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
-use log::{debug, info};
-use niku::object::ObjectKind;
-use niku::peer::Peer;
-use tokio::try_join;
+use console::Term;
+use log::{debug, info, warn};
+use niku::object::{
+    FolderFormat, ListingEntryKind, ObjectCode, ObjectEntry, ObjectKind, ObjectListing,
+};
+use niku::peer::{ConflictPolicy, EntrySelection, ExtractLimits, Peer, PeerError};
+use niku::progress::{DownloadProgress, ExportProgress};
 
-use super::{Cli, CliError};
+use super::progress::ProgressReporter;
+use super::{Cli, CliError, OnConflict};
+
+/// Options used to download and export a received object.
+pub(super) struct ReceiveOptions {
+    /// The custom output path of the object.
+    pub(super) output: Option<PathBuf>,
+
+    /// Write the object into the standard output.
+    pub(super) to_stdout: bool,
+
+    /// Ask the user before downloading the object and when its output path already exists.
+    pub(super) should_ask: bool,
+
+    /// What to do when the output path already exists.
+    pub(super) on_conflict: OnConflict,
+
+    /// The limits of the extraction of the archive of a folder.
+    pub(super) extract_limits: ExtractLimits,
+
+    /// The selected files and folders of a folder or bundle, all of them if [None].
+    pub(super) selection: Option<EntrySelection>,
+}
 
 impl Cli {
     pub(super) async fn receive(
         id: &str,
-        output: &Option<PathBuf>,
-        should_ask: bool,
+        password: &Option<String>,
+        options: &ReceiveOptions,
     ) -> Result<(), CliError> {
-        let id = id.replace("_", "-");
+        let ReceiveOptions {
+            output,
+            to_stdout,
+            should_ask,
+            on_conflict,
+            extract_limits,
+            selection,
+        } = options;
+
+        let (to_stdout, should_ask, on_conflict) = (*to_stdout, *should_ask, *on_conflict);
+        let selection = selection.as_ref();
+
+        let code: ObjectCode = id.replace("_", "-").parse()?;
 
         let output = match output {
             Some(output) => Some(std::path::absolute(output)?),
@@ -29,73 +67,450 @@ impl Cli {
 
         let peer = Peer::new().await?;
 
-        let object_entry = peer.retrieve_object_entry(&id).await?;
+        let object_entry = peer.retrieve_object_entry(&code).await?;
+
+        if to_stdout && !matches!(object_entry.kind, ObjectKind::File | ObjectKind::Text) {
+            peer.async_drop().await?;
+
+            return Err(CliError::NotAFileForStdout(object_entry.kind));
+        }
+
+        if output.is_some() && matches!(object_entry.kind, ObjectKind::Text) {
+            peer.async_drop().await?;
+
+            return Err(CliError::OutputForText);
+        }
+
+        let selection = match (&object_entry.kind, object_entry.folder_format) {
+            (ObjectKind::File | ObjectKind::Text, _) if selection.is_some() => {
+                warn!(
+                    "The {} has no files or folders to select, '--only' is ignored",
+                    object_entry.kind
+                );
+
+                None
+            }
+
+            (ObjectKind::Folder, Some(format))
+                if selection.is_some() && format != FolderFormat::Collection =>
+            {
+                warn!("The folder was sent as a '{format}' archive, it's downloaded completely to extract the selected files");
+
+                selection
+            }
+
+            _ => selection,
+        };
 
         if should_ask {
-            eprint!(
-                "Download {} '{}' ({})? (Y/n): ",
-                object_entry.kind,
-                object_entry.name,
+            match Cli::ask_download(&peer, &object_entry, &code, password).await {
+                Ok(true) => (),
+                Ok(false) => {
+                    info!("Download canceled!");
+                    peer.async_drop().await?;
+                    return Ok(());
+                }
+                Err(error) => {
+                    peer.async_drop().await?;
+                    return Err(error);
+                }
+            }
+        } else {
+            info!("Downloading {}", describe_object_entry(&object_entry))
+        }
+
+        // The sender is only asked once the user wants to download the object
+        if let Err(error) = Cli::request_approval(&peer, &object_entry, &code, password).await {
+            peer.async_drop().await?;
+
+            return Err(error);
+        }
+
+        let available_bytes = peer.get_available_bytes(&object_entry).await?;
+
+        if available_bytes > 0 {
+            info!(
+                "Resuming the download, {} of {} were already downloaded",
+                niku::format_bytes_with_unit(available_bytes),
                 niku::format_bytes_with_unit(object_entry.size)
             );
+        }
 
-            let answer: String = text_io::read!("{}\n");
-            let answer = answer.to_lowercase();
+        let progress_reporter = ProgressReporter::new("Downloading object");
+
+        let on_download_progress = |progress| match progress {
+            DownloadProgress::Connected => debug!("Connected to the sender peer"),
+            DownloadProgress::Total(total) => progress_reporter.set_total(total),
+            DownloadProgress::Received(received) => progress_reporter.set_position(received),
+            DownloadProgress::Done => progress_reporter.finish(),
+        };
+
+        tokio::select! {
+            result = peer.download_object_entry(&object_entry, selection, on_download_progress) => result?,
+
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!();
+                info!("Download interrupted! Run the same command again to resume it");
+                peer.async_drop().await?;
 
-            if !["y", "yes", ""].contains(&answer.as_str()) {
-                info!("Download canceled!");
                 return Ok(());
             }
-        } else {
-            info!(
-                "Downloading {} '{}' ({})",
-                object_entry.kind,
-                object_entry.name,
-                niku::format_bytes_with_unit(object_entry.size)
-            )
         }
 
-        let (task, token) = crate::cli::generic_wait("Downloading object").await;
+        let progress_reporter = ProgressReporter::new("Exporting object");
 
-        peer.download_object_entry(&object_entry).await?;
+        let on_export_progress = |progress| match progress {
+            ExportProgress::Total(total) => progress_reporter.set_total(total),
+            ExportProgress::Extracting(total) => {
+                progress_reporter.restart("Extracting folder", total)
+            }
+            ExportProgress::Exported(exported) => progress_reporter.set_position(exported),
+            ExportProgress::Done => progress_reporter.finish(),
+        };
 
-        token.cancel();
-        try_join!(task)?;
+        if to_stdout || matches!(object_entry.kind, ObjectKind::Text) {
+            if let ObjectKind::Text = object_entry.kind {
+                let text = unsafe { peer.read_text_object_entry(&object_entry).await? };
+
+                print_text(&text)?;
+            } else {
+                unsafe {
+                    peer.write_file_object_entry(
+                        &object_entry,
+                        tokio::io::stdout(),
+                        on_export_progress,
+                    )
+                    .await?;
+                }
+
+                info!(
+                    "Done! Object '{}' written into the standard output",
+                    object_entry.name
+                );
+            }
+
+            debug!("Removing the downloaded data from the cache...");
+            peer.remove_object_entry_data(&object_entry).await?;
+
+            peer.async_drop().await?;
+
+            return Ok(());
+        }
+
+        let conflict_policy = match on_conflict {
+            OnConflict::Policy(conflict_policy) => conflict_policy,
+            OnConflict::Ask if should_ask => ConflictPolicy::Fail,
+            OnConflict::Ask => ConflictPolicy::Rename,
+        };
 
-        let (task, token) = crate::cli::generic_wait("Exporting object").await;
+        let result = Cli::export_object_entry(
+            &peer,
+            &object_entry,
+            &output,
+            conflict_policy,
+            extract_limits,
+            selection,
+            &on_export_progress,
+        )
+        .await;
 
-        let (output_path, file_to_be_deleted_path) = match &object_entry.kind {
-            ObjectKind::File => unsafe {
+        let (output_path, file_to_be_deleted_path) = match result {
+            Err(CliError::PeerError(PeerError::OutputAlreadyExists(path)))
+                if on_conflict == OnConflict::Ask && should_ask =>
+            {
+                Cli::export_object_entry(
+                    &peer,
+                    &object_entry,
+                    &output,
+                    Cli::ask_conflict_policy(&path),
+                    extract_limits,
+                    selection,
+                    &on_export_progress,
+                )
+                .await?
+            }
+
+            result => result?,
+        };
+
+        match output_path {
+            Some(output_path) => info!(
+                "Done! Object '{}' downloaded at '{}'",
+                object_entry.name,
+                output_path
+                    .into_os_string()
+                    .into_string()
+                    .map_err(|_| CliError::IntoStringError)?
+            ),
+
+            None => info!("Skipped! Object '{}' already exists", object_entry.name),
+        }
+
+        if let Some(file_to_be_deleted_path) = file_to_be_deleted_path {
+            debug!("Removing temporal file...");
+            tokio::fs::remove_file(file_to_be_deleted_path).await?;
+        }
+
+        debug!("Removing the downloaded data from the cache...");
+        peer.remove_object_entry_data(&object_entry).await?;
+
+        peer.async_drop().await?;
+
+        Ok(())
+    }
+    /// Export a downloaded object entry, resolving the conflicts with the given policy.
+    ///
+    /// Returns the path of the exported object, or [None] if it was skipped,
+    /// and may return the path of a temporal file that must be removed.
+    async fn export_object_entry(
+        peer: &Peer,
+        object_entry: &ObjectEntry,
+        output: &Option<PathBuf>,
+        conflict_policy: ConflictPolicy,
+        extract_limits: &ExtractLimits,
+        selection: Option<&EntrySelection>,
+        on_export_progress: &impl Fn(ExportProgress),
+    ) -> Result<(Option<PathBuf>, Option<PathBuf>), CliError> {
+        let paths = match &object_entry.kind {
+            ObjectKind::File | ObjectKind::Text => unsafe {
                 (
-                    peer.export_file_object_entry(&object_entry, &output)
-                        .await?,
+                    peer.export_file_object_entry(
+                        object_entry,
+                        output,
+                        conflict_policy,
+                        on_export_progress,
+                    )
+                    .await?,
                     None,
                 )
             },
 
             ObjectKind::Folder => unsafe {
-                peer.export_folder_object_entry(&object_entry, &output)
+                match peer
+                    .export_folder_object_entry(
+                        object_entry,
+                        output,
+                        conflict_policy,
+                        extract_limits,
+                        selection,
+                        on_export_progress,
+                    )
                     .await?
+                {
+                    Some((output_path, file_to_be_deleted_path)) => {
+                        (Some(output_path), file_to_be_deleted_path)
+                    }
+                    None => (None, None),
+                }
+            },
+
+            ObjectKind::Bundle => unsafe {
+                (
+                    Some(
+                        peer.export_bundle_object_entry(
+                            object_entry,
+                            output,
+                            conflict_policy,
+                            selection,
+                            on_export_progress,
+                        )
+                        .await?,
+                    ),
+                    None,
+                )
             },
         };
 
-        token.cancel();
-        try_join!(task)?;
+        Ok(paths)
+    }
 
-        info!(
-            "Done! Object '{}' downloaded at '{}'",
-            object_entry.name,
-            output_path
-                .into_os_string()
-                .into_string()
-                .map_err(|_| CliError::IntoStringError)?
-        );
+    /// Print the listing of the files inside of an object without downloading it.
+    pub(super) async fn list(id: &str, password: &Option<String>) -> Result<(), CliError> {
+        let code: ObjectCode = id.replace("_", "-").parse()?;
 
-        if let Some(file_to_be_deleted_path) = file_to_be_deleted_path {
-            debug!("Removing temporal file...");
-            tokio::fs::remove_file(file_to_be_deleted_path).await?;
+        let peer = Peer::new().await?;
+
+        let object_entry = peer.retrieve_object_entry(&code).await?;
+
+        if object_entry.contents.is_some() {
+            Cli::request_approval(&peer, &object_entry, &code, password).await?;
+        }
+
+        match peer.retrieve_object_listing(&object_entry).await? {
+            Some(listing) => print_listing(&listing, &mut io::stdout().lock())?,
+            None => info!(
+                "The {} '{}' has no listing of its files",
+                object_entry.kind, object_entry.name
+            ),
         }
 
+        peer.async_drop().await?;
+
         Ok(())
     }
+
+    /// Authenticate the sender of an object and ask it for the approval to download the object,
+    /// asking the user for the password of protected objects if it wasn't given.
+    async fn request_approval(
+        peer: &Peer,
+        object_entry: &ObjectEntry,
+        code: &ObjectCode,
+        password: &Option<String>,
+    ) -> Result<(), CliError> {
+        if peer.is_approved(object_entry).await? {
+            return Ok(());
+        }
+
+        let password = match password {
+            None if object_entry.requires_password => Some(Cli::ask_password(&format!(
+                "The {} '{}' is protected, password: ",
+                object_entry.kind, object_entry.name
+            ))?),
+            password => password.clone(),
+        };
+
+        peer.request_approval(
+            object_entry,
+            code,
+            password.as_deref(),
+            log_waiting_for_approval,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ask the user whether to download an object,
+    /// showing the listing of its files as many times as requested,
+    /// which requires the approval of the sender.
+    async fn ask_download(
+        peer: &Peer,
+        object_entry: &ObjectEntry,
+        code: &ObjectCode,
+        password: &Option<String>,
+    ) -> Result<bool, CliError> {
+        let has_listing = object_entry.contents.is_some();
+
+        loop {
+            eprint!(
+                "Download {}? ({}): ",
+                describe_object_entry(object_entry),
+                if has_listing { "Y/n/l to list" } else { "Y/n" }
+            );
+
+            let answer: String = text_io::read!("{}\n");
+
+            match answer.to_lowercase().as_str() {
+                "y" | "yes" | "" => return Ok(true),
+
+                "l" | "list" if has_listing => {
+                    Cli::request_approval(peer, object_entry, code, password).await?;
+
+                    if let Some(listing) = peer.retrieve_object_listing(object_entry).await? {
+                        print_listing(&listing, &mut io::stderr().lock())?;
+                    }
+                }
+
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    /// Ask the user what to do with an output path that already exists, skipping it by default.
+    fn ask_conflict_policy(path: &Path) -> ConflictPolicy {
+        eprint!(
+            "'{}' already exists, overwrite, rename or skip it? (o/r/S): ",
+            path.display()
+        );
+
+        let answer: String = text_io::read!("{}\n");
+
+        match answer.to_lowercase().as_str() {
+            "o" | "overwrite" => ConflictPolicy::Overwrite,
+            "r" | "rename" => ConflictPolicy::Rename,
+            _ => ConflictPolicy::Skip,
+        }
+    }
+}
+
+fn log_waiting_for_approval() {
+    info!("Waiting for the sender to approve the download...");
+}
+
+/// Describe an object entry with its kind, name, size and number of files.
+fn describe_object_entry(object_entry: &ObjectEntry) -> String {
+    let size = niku::format_bytes_with_unit(object_entry.size);
+
+    match &object_entry.contents {
+        Some(contents) => format!(
+            "{} '{}' ({size}, {} files)",
+            object_entry.kind, object_entry.name, contents.file_count
+        ),
+        None => format!("{} '{}' ({size})", object_entry.kind, object_entry.name),
+    }
+}
+
+/// Print the listing of the files inside of an object, with the size of each file and a summary.
+fn print_listing(listing: &ObjectListing, writer: &mut impl Write) -> io::Result<()> {
+    let mut files = 0;
+    let mut folders = 0;
+    let mut bytes = 0;
+
+    for entry in &listing.entries {
+        // The paths come from the sender, so they can't control the terminal
+        let path = replace_control_characters(&entry.path, &[]);
+
+        match entry.kind {
+            ListingEntryKind::File => {
+                files += 1;
+                bytes += entry.size;
+
+                let size = niku::format_bytes_with_unit(entry.size);
+                writeln!(writer, "{size:>12}  {path}")?
+            }
+
+            ListingEntryKind::Folder => {
+                folders += 1;
+
+                writeln!(writer, "{:>12}  {path}/", "")?
+            }
+
+            ListingEntryKind::Symlink => writeln!(writer, "{:>12}  {path}", "link")?,
+        }
+    }
+
+    writeln!(
+        writer,
+        "{files} files and {folders} folders ({})",
+        niku::format_bytes_with_unit(bytes)
+    )
+}
+
+/// Replace the control characters of a text, except the allowed ones.
+fn replace_control_characters(text: &str, allowed: &[char]) -> String {
+    text.chars()
+        .map(|character| match character {
+            character if allowed.contains(&character) => character,
+            character if character.is_control() => char::REPLACEMENT_CHARACTER,
+            character => character,
+        })
+        .collect()
+}
+
+/// Print a received text into the standard output.
+///
+/// On a terminal the control characters are replaced, so the sender can't control the terminal,
+/// and the text always ends with a new line.
+fn print_text(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    if Term::stdout().is_term() {
+        let text = replace_control_characters(text, &['\n', '\t']);
+
+        writeln!(stdout, "{}", text.strip_suffix('\n').unwrap_or(&text))?;
+    } else {
+        stdout.write_all(text.as_bytes())?;
+    }
+
+    stdout.flush()
 }
@@ -4,87 +4,412 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::path::PathBuf;
+use std::pin::pin;
 use std::time::Duration;
 
 use anyhow::Result;
-use console::Emoji;
-use log::{debug, info};
-use niku::peer::Peer;
-use tokio::try_join;
+use console::{Emoji, Term};
+use futures_lite::{Stream, StreamExt};
+use log::{debug, info, trace, warn};
+use niku::object::{FolderFormat, ObjectEntry};
+use niku::peer::{ApprovalRequest, PackOptions, Peer, PeerError, MAX_TEXT_BYTES};
+use niku::progress::{PackProgress, ProviderEvent};
+use tokio::io::AsyncReadExt;
+use tokio::sync::{mpsc, oneshot};
 
+use super::progress::ProgressReporter;
 use super::{Cli, CliError};
 
+/// Path used to send the standard input.
+const STDIN_PATH: &str = "-";
+
+/// Name of the texts sent without a name.
+const DEFAULT_TEXT_NAME: &str = "text";
+
 #[cfg(debug_assertions)]
 const KEEP_ALIVE_OBJECT_SECONDS: u64 = 2;
 
 #[cfg(not(debug_assertions))]
 const KEEP_ALIVE_OBJECT_SECONDS: u64 = 2 * 60;
 
-impl Cli {
-    pub(super) async fn send(path: &Path) -> Result<(), CliError> {
-        let mut peer = Peer::new().await?;
+/// Options of how an object is served to its receivers.
+pub(super) struct ServeOptions {
+    /// Ask the user for the approval of each receiver.
+    pub(super) ask: bool,
 
-        let path = fs::canonicalize(path)?;
+    /// The number of times that the object can be downloaded, unlimited if [None].
+    pub(super) max_downloads: Option<u64>,
 
-        let (object_entry, file_to_be_deleted_path) = if path.is_file() {
-            (unsafe { peer.create_file_object_entry(path).await? }, None)
-        } else if path.is_dir() {
-            let (task, token) = crate::cli::generic_wait("Compressing folder").await;
+    /// The password that the receivers must know, only for protected objects.
+    pub(super) password: Option<String>,
+}
 
-            let (object_entry, file_to_be_deleted_path) =
-                unsafe { peer.create_folder_object_entry(path).await? };
+impl Cli {
+    pub(super) async fn send(
+        paths: &[PathBuf],
+        text: &Option<String>,
+        name: &Option<String>,
+        folder_format: FolderFormat,
+        pack_options: &PackOptions,
+        serve_options: &ServeOptions,
+    ) -> Result<(), CliError> {
+        let ServeOptions {
+            ask,
+            max_downloads,
+            password,
+        } = serve_options;
 
-            token.cancel();
-            try_join!(task)?;
+        // The answers are read from the terminal, as the standard input may be the sent object
+        if *ask && !Term::stderr().is_term() {
+            return Err(CliError::AskWithoutTerminal);
+        }
 
-            (object_entry, Some(file_to_be_deleted_path))
+        let text = match text {
+            Some(text) if text == STDIN_PATH => Some(read_stdin_text().await?),
+            text => text.clone(),
+        };
+
+        let stdin_name = if paths.iter().any(|path| path.as_os_str() == STDIN_PATH) {
+            if paths.len() > 1 {
+                return Err(CliError::StdinWithOtherPaths);
+            }
+
+            Some(name.clone().ok_or(CliError::MissingStdinName)?)
         } else {
-            return Err(CliError::ThePathIsNotAFileOrAFolder);
+            None
         };
 
-        let registered_object_entry = peer.publish_object_entry(&object_entry).await?;
+        let paths = match stdin_name {
+            Some(_) => Vec::new(),
+            None => paths
+                .iter()
+                .map(fs::canonicalize)
+                .collect::<Result<Vec<_>, _>>()?,
+        };
 
-        let object_id_with_whitespaces = registered_object_entry.id.replace("-", " ");
+        if !paths.iter().all(|path| path.is_file() || path.is_dir()) {
+            return Err(CliError::ThePathIsNotAFileOrAFolder);
+        }
 
-        info!(
-            "{} Sending {} '{}'",
-            Emoji("📤 ", " "),
-            object_entry.kind,
-            object_entry.name
-        );
-        info!(
-            " Your ID is: '{}' ({})",
-            object_id_with_whitespaces, registered_object_entry.id
-        );
-        info!("");
-        info!("{} On the other device, please run:", Emoji("📥", " "));
-        info!("  niku receive {}", registered_object_entry.id);
-        info!("");
-        info!("{} Or use one of the official GUI apps:", Emoji("🌐", " "));
-        info!("  https://niku.app/download");
-
-        let mut interval = tokio::time::interval(Duration::from_secs(KEEP_ALIVE_OBJECT_SECONDS));
-
-        loop {
-            tokio::select! {
-                _ = tokio::signal::ctrl_c() => {
-                    break;
-                }
+        let mut peer = Peer::new_ephemeral().await?;
+        let mut file_to_be_deleted_path = None;
+
+        // The ephemeral store and the temporal file are removed even if sending the object fails
+        let result = async {
+            let (mut object_entry, temporal_file_path) =
+                match (text, stdin_name, <[PathBuf; 1]>::try_from(paths)) {
+                    (Some(text), _, _) => {
+                        let name = name.clone().unwrap_or(DEFAULT_TEXT_NAME.to_owned());
+
+                        (peer.create_text_object_entry(name, text).await?, None)
+                    }
+
+                    (None, Some(name), _) => {
+                        let progress_reporter =
+                            ProgressReporter::without_total("Reading standard input");
+
+                        let object_entry = peer
+                            .create_stream_object_entry(name, tokio::io::stdin(), |progress| {
+                                match progress {
+                                    PackProgress::Packed(packed) => {
+                                        progress_reporter.set_position(packed)
+                                    }
+                                    PackProgress::Done => progress_reporter.finish(),
+                                    _ => (),
+                                }
+                            })
+                            .await?;
+
+                        (object_entry, None)
+                    }
+
+                    (None, None, Ok([path])) if path.is_file() => {
+                        (unsafe { peer.create_file_object_entry(path).await? }, None)
+                    }
+
+                    (None, None, Ok([path])) => {
+                        let progress_reporter = ProgressReporter::new(match folder_format {
+                            FolderFormat::Collection => "Importing folder",
+                            FolderFormat::Tar => "Archiving folder",
+                            FolderFormat::Zip | FolderFormat::TarZst => "Compressing folder",
+                        });
+
+                        unsafe {
+                            peer.create_folder_object_entry(
+                                path,
+                                folder_format,
+                                pack_options,
+                                |progress| match progress {
+                                    PackProgress::Skipped { entries, bytes } => {
+                                        log_skipped_entries(entries, bytes)
+                                    }
+                                    PackProgress::Total(total) => {
+                                        progress_reporter.set_total(total)
+                                    }
+                                    PackProgress::File(name) => debug!("Packing '{name}'..."),
+                                    PackProgress::Packed(packed) => {
+                                        progress_reporter.set_position(packed)
+                                    }
+                                    PackProgress::Done => progress_reporter.finish(),
+                                },
+                            )
+                            .await?
+                        }
+                    }
+
+                    (None, None, Err(paths)) => {
+                        let progress_reporter = ProgressReporter::new("Importing files");
+
+                        let object_entry = unsafe {
+                            peer.create_bundle_object_entry(paths, pack_options, |progress| {
+                                match progress {
+                                    PackProgress::Skipped { entries, bytes } => {
+                                        log_skipped_entries(entries, bytes)
+                                    }
+                                    PackProgress::Total(total) => {
+                                        progress_reporter.set_total(total)
+                                    }
+                                    PackProgress::File(name) => debug!("Packing '{name}'..."),
+                                    PackProgress::Packed(packed) => {
+                                        progress_reporter.set_position(packed)
+                                    }
+                                    PackProgress::Done => progress_reporter.finish(),
+                                }
+                            })
+                            .await?
+                        };
+
+                        (object_entry, None)
+                    }
+                };
+
+            file_to_be_deleted_path = temporal_file_path;
+
+            tokio::spawn(log_provider_events(
+                peer.subscribe_provider_events(),
+                object_entry.clone(),
+            ));
+
+            if let Some(password) = password {
+                peer.protect_object_entry(&mut object_entry, password.clone())
+                    .await;
+            }
+
+            if *ask {
+                tokio::spawn(answer_approval_requests(
+                    peer.require_approval().await,
+                    object_entry.name.clone(),
+                ));
+            }
+
+            if let Some(max_downloads) = *max_downloads {
+                peer.limit_downloads(&object_entry, max_downloads).await?;
+            }
+
+            let (registered_object_entry, code) = peer.publish_object_entry(&object_entry).await?;
+
+            let object_id_with_whitespaces = code.to_string().replace("-", " ");
+
+            info!(
+                "{} Sending {} '{}'",
+                Emoji("📤 ", " "),
+                object_entry.kind,
+                object_entry.name
+            );
+            info!(" Your ID is: '{}' ({})", object_id_with_whitespaces, code);
+            info!("");
+            info!("{} On the other device, please run:", Emoji("📥", " "));
+            info!("  niku receive {code}");
+            info!("");
+            info!("{} Or use one of the official GUI apps:", Emoji("🌐", " "));
+            info!("  https://niku.app/download");
+
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(KEEP_ALIVE_OBJECT_SECONDS));
+
+            loop {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {
+                        break;
+                    }
+
+                    _ = peer.wait_for_download_limit(), if max_downloads.is_some() => {
+                        info!("The object has reached its limit of downloads, stopping...");
+                        peer.unregister_object_entry(&registered_object_entry).await?;
+                        break;
+                    }
 
-                _ = interval.tick() => {
-                    debug!("Keeping alive the object...");
-                    peer.keep_alive_object_entry(&registered_object_entry).await?;
+                    _ = interval.tick() => {
+                        debug!("Keeping alive the object...");
+                        peer.keep_alive_object_entry(&registered_object_entry).await?;
+                    }
                 }
             }
+
+            Ok::<(), CliError>(())
         }
+        .await;
 
         if let Some(file_to_be_deleted_path) = file_to_be_deleted_path {
             debug!("Removing temporal file...");
             tokio::fs::remove_file(file_to_be_deleted_path).await?;
         }
 
-        Ok(())
+        peer.async_drop().await?;
+
+        result
+    }
+}
+
+fn log_skipped_entries(entries: u64, bytes: u64) {
+    if entries > 0 {
+        info!(
+            "Skipped {entries} ignored or excluded files and folders ({} of files)",
+            niku::format_bytes_with_unit(bytes)
+        );
+    }
+}
+
+/// Log the events of the transfers of the object to its receivers.
+async fn log_provider_events(
+    provider_events: impl Stream<Item = ProviderEvent>,
+    object_entry: ObjectEntry,
+) {
+    let mut provider_events = pin!(provider_events);
+
+    // The requests of the object itself, and not of other blobs like its listing
+    let mut object_requests = HashSet::new();
+
+    while let Some(event) = provider_events.next().await {
+        match event {
+            ProviderEvent::Connected { node_id } => {
+                info!("The receiver {} has connected", node_id.fmt_short())
+            }
+
+            ProviderEvent::TransferStarted {
+                node_id,
+                request_id,
+                hash,
+            } if hash == object_entry.file_hash => {
+                info!(
+                    "The receiver {} has started downloading the {}",
+                    node_id.fmt_short(),
+                    object_entry.kind
+                );
+                object_requests.insert((node_id, request_id));
+            }
+
+            ProviderEvent::TransferProgress {
+                node_id,
+                request_id,
+                bytes,
+            } if object_requests.contains(&(node_id, request_id)) => trace!(
+                "Sent {} to the receiver {}",
+                niku::format_bytes_with_unit(bytes),
+                node_id.fmt_short()
+            ),
+
+            ProviderEvent::TransferCompleted {
+                node_id,
+                request_id,
+                bytes,
+            } if object_requests.remove(&(node_id, request_id)) => info!(
+                "The receiver {} has finished downloading the {} ({} sent)",
+                node_id.fmt_short(),
+                object_entry.kind,
+                niku::format_bytes_with_unit(bytes)
+            ),
+
+            ProviderEvent::TransferAborted {
+                node_id,
+                request_id,
+            } if object_requests.remove(&(node_id, request_id)) => info!(
+                "The receiver {} has stopped downloading the {}",
+                node_id.fmt_short(),
+                object_entry.kind
+            ),
+
+            ProviderEvent::Disconnected { node_id } => {
+                debug!("The receiver {} has disconnected", node_id.fmt_short())
+            }
+
+            ProviderEvent::AuthenticationFailed {
+                node_id,
+                remaining_attempts,
+                is_locked,
+            } => {
+                if remaining_attempts > 0 {
+                    warn!(
+                        "The receiver {} has failed to authenticate, {remaining_attempts} attempts remaining",
+                        node_id.fmt_short()
+                    );
+                } else {
+                    warn!(
+                        "The receiver {} has failed to authenticate too many times, it won't be served",
+                        node_id.fmt_short()
+                    );
+                }
+
+                if is_locked {
+                    warn!("Too many receivers have failed to authenticate, no new receiver will be served");
+                }
+            }
+
+            _ => (),
+        }
+    }
+}
+
+/// Ask the user whether to approve each receiver that requests to download the object, declining by default.
+async fn answer_approval_requests(
+    mut approval_requests: mpsc::Receiver<ApprovalRequest>,
+    object_name: String,
+) {
+    while let Some(approval_request) = approval_requests.recv().await {
+        let question = format!(
+            "The receiver {} ({}) wants to download '{object_name}', allow it? (y/N): ",
+            approval_request.node_id, approval_request.address
+        );
+
+        // The terminal is read in a thread that is never joined, so the object is kept alive meanwhile
+        // and the sender can still stop while the question is waiting for an answer
+        let (answer_sender, answer) = oneshot::channel();
+        std::thread::spawn(move || {
+            eprint!("{question}");
+
+            let answer = Term::stderr().read_line().unwrap_or_default();
+
+            let _ = answer_sender.send(matches!(answer.to_lowercase().as_str(), "y" | "yes"));
+        });
+
+        let is_allowed = answer.await.unwrap_or(false);
+
+        if is_allowed {
+            info!("The receiver has been allowed to download the object");
+            approval_request.accept();
+        } else {
+            info!("The receiver has been declined");
+            approval_request.decline();
+        }
+    }
+}
+
+/// Read the text to be sent from the standard input, without reading more than the allowed bytes.
+async fn read_stdin_text() -> Result<String, CliError> {
+    let mut text = Vec::new();
+
+    tokio::io::stdin()
+        .take(MAX_TEXT_BYTES + 1)
+        .read_to_end(&mut text)
+        .await?;
+
+    // The length is checked before decoding, as the last character may have been cut
+    if text.len() as u64 > MAX_TEXT_BYTES {
+        return Err(PeerError::TextTooLong.into());
     }
+
+    String::from_utf8(text).map_err(|_| CliError::TextNotUtf8)
 }